@@ -1,4 +1,3 @@
-local etlua = etlua()
 ---comment
 ---@param name string
 ---@param tbl any
@@ -320,6 +319,10 @@ function endsWith(check, ending)
     return ending == "" or check:sub(- #ending) == ending
 end
 
-local template, err = etlua.compile(template)
-if not template then print(err) end
-return template()
+--the same lua vm is used to render multiple pages, so the template only needs to be compiled once
+if not compiled_template then
+    local err
+    compiled_template, err = etlua().compile(template)
+    if not compiled_template then error(err, 0) end
+end
+return compiled_template()
@@ -1,20 +1,36 @@
-use std::{collections::HashMap, fs::read_to_string};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
 
 use anyhow::Context;
 use clap::{Arg, Command};
 use tealr::ToTypename;
 
-#[derive(serde::Serialize, serde::Deserialize, Clone, Default)]
+use crate::{
+    config_format::ConfigFormat,
+    diagnostics::{self, DiagnosticCode, Location, MessageFormat},
+    schema,
+};
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Default, schemars::JsonSchema)]
+/// Template used to generate the html pages
 pub enum TemplateKind {
+    /// The template shipped with tealr_doc_gen
     #[default]
     Builtin,
+    /// Path to a custom lua runner, used together with the builtin template
     FromLua(String),
+    /// Path to a custom etlua template, used together with the builtin runner
     FromTemplate(String),
 }
 
 #[derive(Clone)]
 pub(crate) struct Paths {
     pub(crate) json: String,
+    /// The config file the paths were read from, if there is one
+    pub(crate) config_file: Option<PathBuf>,
     pub(crate) name: String,
     pub(crate) root: String,
     pub(crate) build_dir: String,
@@ -22,11 +38,91 @@ pub(crate) struct Paths {
     pub(crate) def_config: TypeDefFile,
     pub(crate) is_global: bool,
     pub(crate) lua_addon: Option<LuaAddon>,
+    pub(crate) strict: bool,
+    pub(crate) diagnostics: DiagnosticsConfig,
+    pub(crate) message_format: MessageFormat,
+    pub(crate) relative_links: bool,
+    pub(crate) keep_going: bool,
+    pub(crate) sandbox: Option<SandboxConfig>,
+}
+
+pub(crate) const CONFIG_PATH: &str = "./tealr_doc_gen_config.json";
+
+/// The config files that are looked for in the current directory, in order of preference
+const CONFIG_PATHS: [&str; 4] = [
+    CONFIG_PATH,
+    "./tealr_doc_gen_config.toml",
+    "./tealr_doc_gen_config.yaml",
+    "./tealr_doc_gen_config.yml",
+];
+
+/// The config file in the current directory, used when no config file is given
+pub(crate) fn default_config_path() -> PathBuf {
+    let path = CONFIG_PATHS
+        .into_iter()
+        .find(|x| Path::new(x).exists())
+        .unwrap_or(CONFIG_PATH);
+    PathBuf::from(path)
+}
+
+/// Version of the config format. Gets bumped when options are added, renamed or change meaning.
+///
+/// Version 1 is every config file made before `config_version` existed.
+pub(crate) const CONFIG_VERSION: u32 = 2;
+
+impl Paths {
+    /// Every file besides the config file that gets read while generating the documentation
+    pub(crate) fn input_files(&self) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        if self.json != "-" {
+            files.push(PathBuf::from(&self.json));
+        }
+        match &self.template_kind {
+            TemplateKind::Builtin => (),
+            TemplateKind::FromLua(x) | TemplateKind::FromTemplate(x) => files.push(x.into()),
+        }
+        if let DefTemplateRunnerKind::Custom(x) = &self.def_config.runner {
+            files.push(x.into());
+        }
+        for config in self.def_config.templates.values() {
+            if let DefTemplateKind::Custom(x) = &config.template {
+                files.push(x.into());
+            }
+        }
+        files
+    }
 }
 
-#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+/// Decides which diagnostics are reported and which ones fail the run
+pub struct DiagnosticsConfig {
+    /// Diagnostic codes that are never reported
+    #[serde(default)]
+    pub allow: Vec<DiagnosticCode>,
+    /// Diagnostic codes that fail the run, even when not running in strict mode
+    #[serde(default)]
+    pub deny: Vec<DiagnosticCode>,
+}
+
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+/// Limits what templates and runners can do.
+///
+/// Sandboxed templates can't run programs, can only read files inside the project and output folders and can only write inside the output folder
+pub struct SandboxConfig {
+    /// Maximum amount of lua instructions a single page or definition file may take to render
+    #[serde(default)]
+    pub instruction_limit: Option<u64>,
+    /// Maximum amount of memory in bytes a lua vm may use, including the type information given to it
+    #[serde(default)]
+    pub memory_limit: Option<usize>,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+/// Lua code that runs the definition file templates
 pub enum DefTemplateRunnerKind {
+    /// The runner shipped with tealr_doc_gen
     Builtin,
+    /// Path to a custom runner
     Custom(String),
 }
 #[derive(
@@ -38,6 +134,7 @@ pub enum DefTemplateRunnerKind {
     serde::Deserialize,
     tealr::mlu::FromToLua,
     ToTypename,
+    schemars::JsonSchema,
 )]
 /// Template used to generate a definition file
 pub enum DefTemplateKind {
@@ -54,21 +151,25 @@ pub enum DefTemplateKind {
     serde::Deserialize,
     tealr::mlu::FromToLua,
     ToTypename,
+    schemars::JsonSchema,
 )]
 /// The configuration for the definition files that get generated.
 ///
 /// Contain the information needed to link to them
 pub struct DefTemplateConfig {
     /// File extension used
-    pub(crate) extension: String,
+    pub extension: String,
     /// template used
-    pub(crate) template: DefTemplateKind,
+    pub template: DefTemplateKind,
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Clone)]
+#[derive(serde::Serialize, serde::Deserialize, Clone, schemars::JsonSchema)]
+#[serde(default)]
+/// Which definition files get generated and how
 pub struct TypeDefFile {
-    pub(crate) runner: DefTemplateRunnerKind,
-    pub(crate) templates: HashMap<String, DefTemplateConfig>,
+    pub runner: DefTemplateRunnerKind,
+    /// Every definition file that gets generated
+    pub templates: HashMap<String, DefTemplateConfig>,
 }
 
 impl Default for TypeDefFile {
@@ -90,31 +191,183 @@ impl Default for TypeDefFile {
     }
 }
 
-#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[derive(serde::Serialize, schemars::JsonSchema)]
+#[serde(untagged)]
+/// How a turned off lua language server addon is written in the config file
+enum DisabledLuaAddon {
+    /// `{ "enabled": false }`. TOML has no `null`, so this is what gets written
+    Explicit { enabled: bool },
+    /// `false`, as written by older versions
+    Legacy(bool),
+}
+
+fn serialize_disabled_lua_addon<S: serde::Serializer>(serializer: S) -> Result<S::Ok, S::Error> {
+    serde::Serialize::serialize(&DisabledLuaAddon::Explicit { enabled: false }, serializer)
+}
+
+/// Every key of the `lua_addon` table, which [LuaAddon] gets read through.
+///
+/// Serde buffers untagged enums, which hides the unknown keys inside of them from `serde_ignored`.
+/// Reading the keys through a struct instead lets typos inside `lua_addon` be reported like every other unknown key.
+#[derive(serde::Deserialize)]
+struct LuaAddonKeys {
+    /// `false` turns the addon off, `true` is the same as leaving it out
+    enabled: Option<bool>,
+    #[serde(default)]
+    words: Vec<String>,
+    #[serde(default)]
+    files: Vec<String>,
+    #[serde(default)]
+    settings: HashMap<String, serde_json::Value>,
+    #[serde(default)]
+    generic_class_fallback: bool,
+    #[serde(default)]
+    enum_tables: BTreeMap<String, String>,
+}
+
+impl<'de> serde::Deserialize<'de> for LuaAddon {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = LuaAddon;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("the settings of the lua addon, or `{ enabled = false }`")
+            }
+
+            //written by older versions to turn the addon off
+            fn visit_bool<E: serde::de::Error>(self, v: bool) -> Result<Self::Value, E> {
+                if v {
+                    Err(E::custom(
+                        "the lua addon is turned on by giving its settings",
+                    ))
+                } else {
+                    Ok(LuaAddon::False)
+                }
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(
+                self,
+                map: A,
+            ) -> Result<Self::Value, A::Error> {
+                let keys: LuaAddonKeys = serde::Deserialize::deserialize(
+                    serde::de::value::MapAccessDeserializer::new(map),
+                )?;
+                if keys.enabled == Some(false) {
+                    return Ok(LuaAddon::False);
+                }
+                Ok(LuaAddon::Create {
+                    words: keys.words,
+                    files: keys.files,
+                    settings: keys.settings,
+                    generic_class_fallback: keys.generic_class_fallback,
+                    enum_tables: keys.enum_tables,
+                })
+            }
+        }
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+/// Writes the settings sorted and without `null` values, as TOML can't express those.
+///
+/// The language server treats a setting that is `null` the same as one that is left out.
+fn serialize_lua_addon_settings<S: serde::Serializer>(
+    settings: &HashMap<String, serde_json::Value>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    fn without_nulls(value: &serde_json::Value) -> serde_json::Value {
+        match value {
+            serde_json::Value::Object(x) => serde_json::Value::Object(
+                x.iter()
+                    .filter(|(_, value)| !value.is_null())
+                    .map(|(key, value)| (key.clone(), without_nulls(value)))
+                    .collect(),
+            ),
+            serde_json::Value::Array(x) => {
+                serde_json::Value::Array(x.iter().map(without_nulls).collect())
+            }
+            x => x.clone(),
+        }
+    }
+    serializer.collect_map(
+        settings
+            .iter()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(key, value)| (key, without_nulls(value)))
+            .collect::<BTreeMap<_, _>>(),
+    )
+}
+
+#[derive(Clone, serde::Serialize, schemars::JsonSchema)]
 #[serde(untagged)]
+/// The lua language server addon that gets generated
 pub enum LuaAddon {
+    /// Don't generate the addon. Written as `{ "enabled": false }`
+    #[serde(serialize_with = "serialize_disabled_lua_addon")]
+    #[schemars(with = "DisabledLuaAddon")]
     False,
+    /// The values that are copied into the `plugin.json` of the addon
     Create {
+        /// Words that make the language server suggest the addon when they show up in a file
+        #[serde(default)]
         words: Vec<String>,
+        /// Files that make the language server suggest the addon when they exist
+        #[serde(default)]
         files: Vec<String>,
+        /// Settings that get applied when the addon is used
+        #[serde(default, serialize_with = "serialize_lua_addon_settings")]
         settings: HashMap<String, serde_json::Value>,
+        /// Declare generic classes as `any` aliases instead of as real generic classes, for versions of lua-language-server without support for them
+        #[serde(default)]
+        generic_class_fallback: bool,
+        /// Enums that the api also exposes as a table, with a field for every variant that holds that variant. Maps the name of the enum to the lua path of its table, like `Color = "my_api.Color"`. These are written as `---@enum` tables instead of as aliases
+        #[serde(default)]
+        enum_tables: BTreeMap<String, String>,
     },
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
+fn unversioned_config() -> u32 {
+    1
+}
+
+#[derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(default)]
+/// The config file of tealr_doc_gen. Every option that is left out uses its default
 pub struct Config {
+    /// Path to the JSON Schema of this file, only used by editors
+    #[serde(rename = "$schema", skip_serializing_if = "Option::is_none")]
+    schema: Option<String>,
+    /// Version of the config format the file was written for. Files without it are version 1
+    #[serde(default = "unversioned_config")]
+    config_version: u32,
     doc_template: TemplateKind,
+    /// Sub folder the documentation is hosted under, like `tealsql` for `https://lenscas.github.io/tealsql/`
     page_root: String,
+    /// Folder to store the documentation in
     store_in: String,
+    /// Name of the project. The types are read from `<name>.json` and it is used to name the definition files and addon
     name: String,
     type_def_files: TypeDefFile,
+    /// The lua language server addon. Config files without it don't generate the addon, like before `config_version` existed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     lua_addon: Option<LuaAddon>,
+    /// If the api is directly accessible in the global scope, instead of having to be loaded like a library
     is_global: bool,
+    /// Fail when any diagnostic gets reported
+    strict: bool,
+    diagnostics: DiagnosticsConfig,
+    /// Makes every link relative to the page it is on, so the pages also work when opened from disk or served from a different path
+    relative_links: bool,
+    /// Runs templates and runners in a sandbox. Leaving it out gives them full access to the system
+    sandbox: Option<SandboxConfig>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            schema: None,
+            config_version: CONFIG_VERSION,
             doc_template: Default::default(),
             page_root: Default::default(),
             is_global: true,
@@ -125,125 +378,360 @@ impl Default for Config {
                 words: Vec::new(),
                 files: Vec::new(),
                 settings: HashMap::new(),
+                generic_class_fallback: false,
+                enum_tables: BTreeMap::new(),
             }),
+            strict: false,
+            diagnostics: Default::default(),
+            relative_links: false,
+            sandbox: None,
         }
     }
 }
 
+impl Config {
+    /// Makes relative paths to custom templates and runners start at the folder the config file is in, instead of the current directory
+    fn resolve_paths(&mut self, config_folder: &Path) {
+        let resolve = |path: &mut String| {
+            if Path::new(path.as_str()).is_relative() {
+                *path = config_folder.join(&path).to_string_lossy().into_owned();
+            }
+        };
+        match &mut self.doc_template {
+            TemplateKind::Builtin => (),
+            TemplateKind::FromLua(x) | TemplateKind::FromTemplate(x) => resolve(x),
+        }
+        if let DefTemplateRunnerKind::Custom(x) = &mut self.type_def_files.runner {
+            resolve(x);
+        }
+        for config in self.type_def_files.templates.values_mut() {
+            if let DefTemplateKind::Custom(x) = &mut config.template {
+                resolve(x);
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+/// The arguments given to the `run` and `serve` subcommands
+pub(crate) struct RunArgs {
+    strict: bool,
+    message_format: MessageFormat,
+    keep_going: bool,
+    /// Where to read the config file from instead of [default_config_path]
+    config: Option<PathBuf>,
+    /// Where to read the json from instead of `<name>.json`. `-` reads it from stdin
+    json: Option<String>,
+    /// Folder to store the documentation in, overriding `store_in`
+    out: Option<String>,
+}
+
+impl RunArgs {
+    /// The config file that gets used
+    pub(crate) fn config_path(&self) -> PathBuf {
+        self.config.clone().unwrap_or_else(default_config_path)
+    }
+
+    /// Reads the config file and combines it with the arguments
+    pub(crate) fn load_paths(&self) -> Result<Paths, anyhow::Error> {
+        let config_path = self.config_path();
+        let mut config: Config = read_config(&config_path)?;
+        config.resolve_paths(config_path.parent().unwrap_or(Path::new(".")));
+        Ok(Paths {
+            config_file: Some(config_path),
+            is_global: config.is_global,
+            json: self
+                .json
+                .clone()
+                .unwrap_or_else(|| config.name.clone() + ".json"),
+            build_dir: self.out.clone().unwrap_or(config.store_in),
+            name: config.name,
+            root: config.page_root,
+            template_kind: config.doc_template,
+            def_config: config.type_def_files,
+            lua_addon: config.lua_addon,
+            strict: config.strict || self.strict,
+            diagnostics: config.diagnostics,
+            message_format: self.message_format,
+            relative_links: config.relative_links,
+            keep_going: self.keep_going,
+            sandbox: config.sandbox,
+        })
+    }
+}
+
 pub(crate) enum Modes {
     Credits,
     GenerateDocs(Box<Paths>),
-    SelfDocTemplate {
-        build_dir: String,
-    },
-    SelfDefTemplate {
-        build_dir: String,
-    },
-    GenFile {
-        file: String,
-        location: &'static str,
-    },
+    Watch(RunArgs),
+    Serve { args: RunArgs, port: u16 },
+    SelfDocTemplate { build_dir: String },
+    SelfDefTemplate { build_dir: String },
+    GenFile { file: String, location: PathBuf },
     Nothing,
 }
 
+/// The arguments of the `run` subcommand that `serve` takes as well
+fn run_args() -> [Arg<'static>; 6] {
+    [
+        Arg::new("strict")
+            .long("strict")
+            .help("Exits with a non-zero exit code if any diagnostic got reported"),
+        Arg::new("message_format")
+            .long("message-format")
+            .takes_value(true)
+            .possible_values(["human", "json", "sarif"])
+            .default_value("human")
+            .help("How the diagnostics get reported. `json` and `sarif` are printed to stdout"),
+        Arg::new("keep_going")
+            .long("keep-going")
+            .help("Keeps generating the other pages and definition files when one fails. Every failure is reported at the end"),
+        Arg::new("config")
+            .long("config")
+            .takes_value(true)
+            .help("The config file to use instead of tealr_doc_gen_config.json, .toml or .yaml in the current directory. Relative template paths in it start at the folder it is in"),
+        Arg::new("json")
+            .long("json")
+            .takes_value(true)
+            .help("The json file with the types to document instead of <name>.json in the current directory. Use `-` to read it from stdin"),
+        Arg::new("out")
+            .long("out")
+            .takes_value(true)
+            .help("Folder to store the documentation in. Overrides `store_in` from the config file"),
+    ]
+}
+
+impl RunArgs {
+    /// Reads the arguments added by [run_args]
+    fn from_matches(matches: &clap::ArgMatches) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            strict: matches.contains_id("strict"),
+            message_format: matches
+                .get_one::<String>("message_format")
+                .map(|x| x.parse())
+                .transpose()?
+                .unwrap_or_default(),
+            keep_going: matches.contains_id("keep_going"),
+            config: matches.get_one::<String>("config").map(PathBuf::from),
+            json: matches.get_one::<String>("json").cloned(),
+            out: matches.get_one::<String>("out").cloned(),
+        })
+    }
+
+    /// Errors when the json comes from stdin, which can only be read once while watching needs to read it again
+    fn check_watchable(&self, flag: &str) -> Result<(), anyhow::Error> {
+        if self.json.as_deref() == Some("-") {
+            return Err(anyhow::anyhow!(
+                "`--json -` can't be combined with `{flag}`, as stdin can only be read once"
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn cli() -> Command<'static> {
+    clap::App::new("tealr doc gen")
+        .subcommand(
+            Command::new("run")
+                .alias("gen")
+                .about("Generates the documentation pages")
+                .args(run_args())
+                .arg(
+                    Arg::new("watch")
+                        .long("watch")
+                        .short('w')
+                        .help("Keeps running and generates the documentation again when the json, config file or any of the templates change"),
+                ),
+        )
+        .subcommand(
+            Command::new("serve")
+                .about("Generates the documentation and serves it on localhost. Pages reload when the documentation is generated again")
+                .args(run_args())
+                .arg(
+                    Arg::new("port")
+                        .long("port")
+                        .takes_value(true)
+                        .default_value("8000")
+                        .help("The port to listen on"),
+                ),
+        )
+        .subcommand(
+            Command::new("gen-self")
+                .about("Generates files used to add custom behavior to tealr_doc_gen")
+                .arg(Arg::new("docs_documentation_template").long("docs-documentation-template").help(
+                    "Generates the documentation for the lua api exposed to the template used to generate documentation pages.",
+                )).arg(
+                    Arg::new("docs_definition_template")
+                    .long("docs-definition-template")
+                    .help("Generates the documentation for the lua api exposed to the template used to generate definition files.")
+                )
+                .arg(
+                    Arg::new("config")
+                        .long("config")
+                        .help("Generates a new config file"),
+                )
+                .arg(Arg::new("doc_template").long("doc-template").help(
+                    "Generates the default template used to generate the documentation pages",
+                ))
+                .arg(
+                    Arg::new("lua_runner")
+                        .long("lua-runner")
+                        .help("Generate the lua code that loads and executes the template."),
+                )
+                .arg(
+                    Arg::new("definition_template")
+                        .long("definition-template")
+                        .help("Generates the default template used to generate the teal definition file.")
+                )
+                .arg(
+                    Arg::new("config_schema")
+                        .long("config-schema")
+                        .help("Generates the JSON Schema of the config file, for editors to validate and autocomplete it"),
+                )
+                .arg(
+                    Arg::new("input_schema")
+                        .long("input-schema")
+                        .help("Generates the JSON Schema of the json file with the types to document"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(["json", "toml", "yaml"])
+                        .default_value("json")
+                        .help("The format of the config file generated by --config. The toml version explains every option in comments"),
+                )
+                .arg(
+                    Arg::new("print")
+                        .long("print")
+                        .short('p')
+                        .help("Prints the file instead of writing it directly to a file"),
+                ),
+        )
+        .subcommand(
+            Command::new("migrate-config")
+                .about("Rewrites the config file to the current config_version, filling in the default of every missing option and removing unknown keys")
+                .arg(
+                    Arg::new("config")
+                        .long("config")
+                        .takes_value(true)
+                        .help("The config file to migrate instead of tealr_doc_gen_config.json, .toml or .yaml in the current directory"),
+                )
+                .arg(
+                    Arg::new("print")
+                        .long("print")
+                        .short('p')
+                        .help("Prints the migrated config file instead of overwriting it"),
+                ),
+        )
+        .arg(
+            Arg::new("credits")
+                .long("credits")
+                .short('c')
+                .help("Shows who worked on tealr_doc_gen"),
+        )
+}
+
 pub(crate) fn get_paths() -> Result<Modes, anyhow::Error> {
-    let matches =
-        clap::App::new("tealr doc gen")
-            .subcommand(
-                Command::new("run")
-                    .alias("gen")
-                    .about("Generates the documentation pages"),
-            )
-            .subcommand(
-                Command::new("gen-self")
-                    .about("Generates files used to add custom behavior to tealr_doc_gen")
-                    .arg(Arg::new("docs_documentation_template").long("docs-documentation-template").help(
-                        "Generates the documentation for the lua api exposed to the template used to generate documentation pages.",
-                    )).arg(
-                        Arg::new("docs_definition_template")
-                        .long("docs-definition-template")
-                        .help("Generates the documentation for the lua api exposed to the template used to generate definition files.")
-                    )
-                    .arg(
-                        Arg::new("config")
-                            .long("config")
-                            .help("Generates a new config file"),
-                    )
-                    .arg(Arg::new("doc_template").long("doc-template").help(
-                        "Generates the default template used to generate the documentation pages",
-                    ))
-                    .arg(
-                        Arg::new("lua_runner")
-                            .long("lua-runner")
-                            .help("Generate the lua code that loads and executes the template."),
-                    )
-                    .arg(
-                        Arg::new("definition_template")
-                            .long("definition-template")
-                            .help("Generates the default template used to generate the teal definition file.")
-                    )
-                    .arg(
-                        Arg::new("print")
-                            .long("print")
-                            .short('p')
-                            .help("Prints the file instead of writing it directly to a file"),
-                    ),
-            )
-            .arg(
-                Arg::new("credits")
-                    .long("credits")
-                    .short('c')
-                    .help("Shows who worked on tealr_doc_gen"),
-            )
-            .get_matches();
+    let matches = cli().get_matches();
     if matches.contains_id("credits") {
         return Ok(Modes::Credits);
     }
 
-    if matches.subcommand_matches("run").is_some() {
-        let config: Config = read_config()?;
-        return Ok(Modes::GenerateDocs(Box::new(Paths {
-            is_global: config.is_global,
-            json: config.name.clone() + ".json",
-            build_dir: config.store_in,
-            name: config.name,
-            root: config.page_root,
-            template_kind: config.doc_template,
-            def_config: config.type_def_files,
-            lua_addon: config.lua_addon,
-        })));
+    if let Some(x) = matches.subcommand_matches("run") {
+        let args = RunArgs::from_matches(x)?;
+        if x.contains_id("watch") {
+            args.check_watchable("--watch")?;
+            return Ok(Modes::Watch(args));
+        }
+        return Ok(Modes::GenerateDocs(Box::new(args.load_paths()?)));
+    }
+    if let Some(x) = matches.subcommand_matches("serve") {
+        let port = x
+            .get_one::<String>("port")
+            .map(|x| x.parse())
+            .transpose()
+            .context("Port must be a number")?
+            .unwrap_or(8000);
+        let args = RunArgs::from_matches(x)?;
+        args.check_watchable("serve")?;
+        return Ok(Modes::Serve { args, port });
+    }
+    if let Some(x) = matches.subcommand_matches("migrate-config") {
+        let path = x
+            .get_one::<String>("config")
+            .map(PathBuf::from)
+            .unwrap_or_else(default_config_path);
+        let (mut config, unknown_keys) = parse_config(&path)?;
+        for key in unknown_keys {
+            eprintln!("Removed unknown key `{key}`");
+        }
+        eprintln!(
+            "Migrated {} from config_version {} to {CONFIG_VERSION}",
+            path.display(),
+            config.config_version
+        );
+        config.config_version = CONFIG_VERSION;
+        let text = ConfigFormat::from_path(&path).to_string(&config)?;
+        if x.contains_id("print") {
+            println!("{}", text);
+            return Ok(Modes::Nothing);
+        }
+        return Ok(Modes::GenFile {
+            file: text,
+            location: path,
+        });
     }
     if let Some(x) = matches.subcommand_matches("gen-self") {
         if x.contains_id("docs_documentation_template") {
-            let config = read_config()?;
+            let config = read_config(&default_config_path())?;
             return Ok(Modes::SelfDocTemplate {
                 build_dir: config.store_in,
             });
         } else if x.contains_id("docs_definition_template") {
-            let config = read_config()?;
+            let config = read_config(&default_config_path())?;
             return Ok(Modes::SelfDefTemplate {
                 build_dir: config.store_in,
             });
         }
-        let (text, location) = if x.contains_id("config") {
+        let (text, location): (String, PathBuf) = if x.contains_id("config") {
+            let format = x
+                .get_one::<String>("format")
+                .map(|x| x.parse())
+                .transpose()?
+                .unwrap_or(ConfigFormat::Json);
+            let text = match format {
+                ConfigFormat::Toml => include_str!("../base_config.toml").to_string(),
+                ConfigFormat::Json | ConfigFormat::Yaml => format.to_string(&Config::default())?,
+            };
+            (
+                text,
+                format!("./tealr_doc_gen_config.{}", format.extension()).into(),
+            )
+        } else if x.contains_id("config_schema") {
+            (
+                schema::config_schema()?,
+                "./tealr_doc_gen_config.schema.json".into(),
+            )
+        } else if x.contains_id("input_schema") {
             (
-                serde_json::to_string_pretty(&Config::default())?,
-                "./tealr_doc_gen_config.json",
+                schema::input_schema()?,
+                "./tealr_doc_gen_input.schema.json".into(),
             )
         } else if x.contains_id("doc_template") {
             (
                 include_str!("../base_template.etlua").into(),
-                "./template.etlua",
+                "./template.etlua".into(),
             )
         } else if x.contains_id("definition_template") {
             (
                 include_str!("../base_teal_definition_template.etlua").into(),
-                "./teal_definition_template.etlua",
+                "./teal_definition_template.etlua".into(),
             )
         } else if x.contains_id("lua_runner") {
             (
                 include_str!("../base_run_template.lua").into(),
-                "./run_template.lua",
+                "./run_template.lua".into(),
             )
         } else {
             return Err(anyhow::anyhow!("Missing argument"));
@@ -260,9 +748,379 @@ pub(crate) fn get_paths() -> Result<Modes, anyhow::Error> {
     Ok(Modes::Nothing)
 }
 
-fn read_config() -> Result<Config, anyhow::Error> {
-    serde_json::from_str(
-        &read_to_string("./tealr_doc_gen_config.json")
-            .context("Could not read tealr_doc_gen_config.json in current directory. Maybe generate one using `tealr_doc_gen gen_self --config`?")?
-    ).context("Error while parsing the config file. Use `tealr_doc_gen gen_self --config` to generate an example")
+/// Parses the config file, returning the path of every key that is not part of the config next to it
+fn parse_config(path: &Path) -> Result<(Config, Vec<String>), anyhow::Error> {
+    let text = read_to_string(path)
+        .with_context(|| format!("Could not read the config file at {}. Maybe generate one using `tealr_doc_gen gen_self --config`?", path.display()))?;
+    let mut unknown_keys = Vec::new();
+    let config: Config = ConfigFormat::from_path(path)
+        .parse(&text, |key| unknown_keys.push(key.to_string()))
+        .context("Error while parsing the config file. Use `tealr_doc_gen gen_self --config` to generate an example")?;
+    if config.config_version > CONFIG_VERSION {
+        return Err(anyhow::anyhow!(
+            "{} uses config_version {}, but this version of tealr_doc_gen only knows up to config_version {CONFIG_VERSION}. Please update tealr_doc_gen",
+            path.display(),
+            config.config_version
+        ));
+    }
+    Ok((config, unknown_keys))
+}
+
+/// Every key that can appear in the config file
+fn known_config_keys() -> Vec<String> {
+    fn collect(value: &serde_json::Value, keys: &mut Vec<String>) {
+        match value {
+            serde_json::Value::Object(x) => {
+                for (key, value) in x {
+                    keys.push(key.clone());
+                    collect(value, keys);
+                }
+            }
+            serde_json::Value::Array(x) => x.iter().for_each(|x| collect(x, keys)),
+            _ => (),
+        }
+    }
+    let config = Config {
+        sandbox: Some(Default::default()),
+        ..Default::default()
+    };
+    //only written when the addon is turned off
+    let mut keys = vec!["enabled".to_owned()];
+    if let Ok(value) = serde_json::to_value(config) {
+        collect(&value, &mut keys);
+    }
+    keys
+}
+
+/// Reads the config file, reporting unknown keys and outdated config versions as diagnostics
+fn read_config(path: &Path) -> Result<Config, anyhow::Error> {
+    let (config, unknown_keys) = parse_config(path)?;
+    let location = || {
+        Some(Location::Input {
+            path: path.to_string_lossy().into_owned(),
+        })
+    };
+    if !unknown_keys.is_empty() {
+        let known_keys = known_config_keys();
+        for key in unknown_keys {
+            let name = key.rsplit('.').next().unwrap_or(&key);
+            let message = match diagnostics::did_you_mean(name, &known_keys) {
+                Some(x) => format!("Unknown key `{key}` in the config file. Did you mean `{x}`?"),
+                None => format!("Unknown key `{key}` in the config file. It is ignored"),
+            };
+            diagnostics::report_once(DiagnosticCode::Config, &key, location(), message);
+        }
+    }
+    if config.config_version < CONFIG_VERSION {
+        diagnostics::report_once(
+            DiagnosticCode::Config,
+            "config_version",
+            location(),
+            format!(
+                "The config file uses config_version {}, the current version is {CONFIG_VERSION}. Run `tealr_doc_gen migrate-config` to update it",
+                config.config_version
+            ),
+        );
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_folder;
+
+    #[test]
+    fn serve_takes_the_arguments_of_run() {
+        let matches = cli()
+            .try_get_matches_from([
+                "tealr_doc_gen",
+                "serve",
+                "--config",
+                "docs.toml",
+                "--json",
+                "types.json",
+                "--out",
+                "site",
+                "--strict",
+                "--keep-going",
+                "--message-format",
+                "json",
+                "--port",
+                "9000",
+            ])
+            .unwrap();
+        let serve = matches.subcommand_matches("serve").unwrap();
+        let args = RunArgs::from_matches(serve).unwrap();
+        assert_eq!(args.config, Some(PathBuf::from("docs.toml")));
+        assert_eq!(args.json.as_deref(), Some("types.json"));
+        assert_eq!(args.out.as_deref(), Some("site"));
+        assert!(args.strict);
+        assert!(args.keep_going);
+        assert!(args.message_format == MessageFormat::Json);
+        assert_eq!(serve.get_one::<String>("port").unwrap(), "9000");
+    }
+
+    #[test]
+    fn serve_can_not_read_the_json_from_stdin() {
+        let matches = cli()
+            .try_get_matches_from(["tealr_doc_gen", "serve", "--json", "-"])
+            .unwrap();
+        let args = RunArgs::from_matches(matches.subcommand_matches("serve").unwrap()).unwrap();
+        assert!(args.check_watchable("serve").is_err());
+    }
+
+    fn to_json(config: &Config) -> serde_json::Value {
+        serde_json::to_value(config).unwrap()
+    }
+
+    fn round_trip(format: ConfigFormat, config: &Config) -> Config {
+        let text = format.to_string(config).unwrap();
+        format
+            .parse(&text, |key| panic!("unknown key {key} in\n{text}"))
+            .unwrap()
+    }
+
+    fn configs() -> Vec<Config> {
+        let mut settings = HashMap::new();
+        settings.insert(
+            "Lua.diagnostics.globals".to_owned(),
+            serde_json::json!(["my_global"]),
+        );
+        vec![
+            Config::default(),
+            Config {
+                lua_addon: None,
+                ..Default::default()
+            },
+            Config {
+                lua_addon: Some(LuaAddon::False),
+                ..Default::default()
+            },
+            Config {
+                lua_addon: Some(LuaAddon::Create {
+                    words: vec!["my_api".to_owned()],
+                    files: vec![".my_api".to_owned()],
+                    settings,
+                    generic_class_fallback: true,
+                    enum_tables: BTreeMap::from([("Color".to_owned(), "my_api.Color".to_owned())]),
+                }),
+                doc_template: TemplateKind::FromTemplate("template.etlua".to_owned()),
+                sandbox: Some(SandboxConfig {
+                    instruction_limit: Some(1000),
+                    memory_limit: None,
+                }),
+                ..Default::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn configs_survive_every_format() {
+        for format in [ConfigFormat::Json, ConfigFormat::Toml, ConfigFormat::Yaml] {
+            for config in configs() {
+                assert_eq!(to_json(&round_trip(format, &config)), to_json(&config));
+            }
+        }
+    }
+
+    #[test]
+    fn null_settings_are_left_out() {
+        let mut settings = HashMap::new();
+        settings.insert("Lua.runtime.version".to_owned(), serde_json::Value::Null);
+        settings.insert(
+            "Lua.diagnostics".to_owned(),
+            serde_json::json!({ "enable": true, "globals": null }),
+        );
+        let config = Config {
+            lua_addon: Some(LuaAddon::Create {
+                words: Vec::new(),
+                files: Vec::new(),
+                settings,
+                generic_class_fallback: false,
+                enum_tables: BTreeMap::new(),
+            }),
+            ..Default::default()
+        };
+        let parsed = round_trip(ConfigFormat::Toml, &config);
+        let Some(LuaAddon::Create { settings, .. }) = parsed.lua_addon else {
+            panic!("the addon got turned off")
+        };
+        assert_eq!(settings.len(), 1);
+        assert_eq!(
+            settings["Lua.diagnostics"],
+            serde_json::json!({ "enable": true })
+        );
+    }
+
+    #[test]
+    fn the_addon_can_be_turned_off_in_every_way() {
+        for (format, text) in [
+            (ConfigFormat::Json, r#"{ "lua_addon": false }"#),
+            (
+                ConfigFormat::Json,
+                r#"{ "lua_addon": { "enabled": false } }"#,
+            ),
+            (ConfigFormat::Toml, "lua_addon = { enabled = false }"),
+            (ConfigFormat::Yaml, "lua_addon: false"),
+        ] {
+            let config: Config = format.parse(text, |_| ()).unwrap();
+            assert!(matches!(config.lua_addon, Some(LuaAddon::False)), "{text}");
+        }
+        let config: Config = ConfigFormat::Json
+            .parse(r#"{ "lua_addon": null }"#, |_| ())
+            .unwrap();
+        assert!(config.lua_addon.is_none());
+        let config: Config = ConfigFormat::Json
+            .parse(r#"{ "lua_addon": { "words": ["my_api"] } }"#, |_| ())
+            .unwrap();
+        assert!(matches!(config.lua_addon, Some(LuaAddon::Create { .. })));
+        let config: Config = ConfigFormat::Json
+            .parse(r#"{ "lua_addon": { "enabled": true } }"#, |_| ())
+            .unwrap();
+        assert!(matches!(config.lua_addon, Some(LuaAddon::Create { .. })));
+    }
+
+    #[test]
+    fn configs_without_an_addon_keep_not_generating_it() {
+        for text in [r#"{ "name": "my_api" }"#, r#"{ "config_version": 2 }"#] {
+            let config: Config = ConfigFormat::Json.parse(text, |_| ()).unwrap();
+            assert!(config.lua_addon.is_none(), "{text}");
+            assert!(!to_json(&config)
+                .as_object()
+                .unwrap()
+                .contains_key("lua_addon"));
+        }
+    }
+
+    #[test]
+    fn unknown_keys_inside_the_addon_are_found() {
+        for (format, text) in [
+            (
+                ConfigFormat::Json,
+                r#"{ "lua_addon": { "wrods": ["my_api"], "files": [] } }"#,
+            ),
+            (ConfigFormat::Toml, "[lua_addon]\nwrods = [\"my_api\"]"),
+            (ConfigFormat::Yaml, "lua_addon:\n  wrods: [my_api]"),
+        ] {
+            let mut unknown_keys = Vec::new();
+            let config: Config = format
+                .parse(text, |key| unknown_keys.push(key.to_string()))
+                .unwrap();
+            assert_eq!(unknown_keys, ["lua_addon.wrods"], "{text}");
+            assert!(matches!(config.lua_addon, Some(LuaAddon::Create { .. })));
+        }
+        assert_eq!(
+            diagnostics::did_you_mean("wrods", &known_config_keys()),
+            Some("words")
+        );
+    }
+
+    #[test]
+    fn base_config_matches_the_default() {
+        let config: Config = ConfigFormat::Toml
+            .parse(include_str!("../base_config.toml"), |key| {
+                panic!("unknown key {key} in base_config.toml")
+            })
+            .unwrap();
+        assert_eq!(to_json(&config), to_json(&Config::default()));
+    }
+
+    fn custom_templates(path: &str) -> Config {
+        let mut config = Config {
+            doc_template: TemplateKind::FromTemplate(format!("{path}page.etlua")),
+            ..Default::default()
+        };
+        config.type_def_files.runner = DefTemplateRunnerKind::Custom(format!("{path}runner.lua"));
+        config.type_def_files.templates.insert(
+            "custom".into(),
+            DefTemplateConfig {
+                extension: ".lua".into(),
+                template: DefTemplateKind::Custom(format!("{path}def.etlua")),
+            },
+        );
+        config
+    }
+
+    /// The template, runner and custom definition template paths of the config
+    fn template_paths(template_kind: &TemplateKind, def_config: &TypeDefFile) -> Vec<String> {
+        let mut paths = Vec::new();
+        if let TemplateKind::FromTemplate(x) = template_kind {
+            paths.push(x.clone());
+        }
+        if let DefTemplateRunnerKind::Custom(x) = &def_config.runner {
+            paths.push(x.clone());
+        }
+        if let DefTemplateKind::Custom(x) = &def_config.templates["custom"].template {
+            paths.push(x.clone());
+        }
+        paths
+    }
+
+    #[test]
+    fn relative_template_paths_start_at_the_config_folder() {
+        let folder = Path::new("configs").join("docs");
+        let mut config = custom_templates("");
+        config.resolve_paths(&folder);
+        let expected = ["page.etlua", "runner.lua", "def.etlua"]
+            .map(|x| folder.join(x).to_string_lossy().into_owned());
+        assert_eq!(
+            template_paths(&config.doc_template, &config.type_def_files),
+            expected
+        );
+    }
+
+    #[test]
+    fn absolute_template_paths_are_kept() {
+        let root = test_folder("absolute_template_paths_are_kept");
+        let prefix = format!("{}{}", root.to_string_lossy(), std::path::MAIN_SEPARATOR);
+        let mut config = custom_templates(&prefix);
+        config.resolve_paths(Path::new("configs"));
+        let expected = ["page.etlua", "runner.lua", "def.etlua"]
+            .map(|x| root.join(x).to_string_lossy().into_owned());
+        assert_eq!(
+            template_paths(&config.doc_template, &config.type_def_files),
+            expected
+        );
+    }
+
+    fn args_with(config: PathBuf, json: Option<&str>, out: Option<&str>) -> RunArgs {
+        RunArgs {
+            strict: false,
+            message_format: MessageFormat::Human,
+            keep_going: false,
+            config: Some(config),
+            json: json.map(String::from),
+            out: out.map(String::from),
+        }
+    }
+
+    #[test]
+    fn run_arguments_override_the_config_file() {
+        let folder = test_folder("run_arguments_override_the_config_file");
+        let config_path = folder.join("docs.json");
+        let config = Config {
+            name: "my_api".into(),
+            store_in: "pages".into(),
+            ..custom_templates("")
+        };
+        std::fs::write(&config_path, serde_json::to_string(&config).unwrap()).unwrap();
+
+        let paths = args_with(config_path.clone(), None, None)
+            .load_paths()
+            .unwrap();
+        assert_eq!(paths.build_dir, "pages");
+        assert_eq!(paths.json, "my_api.json");
+        assert_eq!(paths.config_file.as_deref(), Some(config_path.as_path()));
+        assert_eq!(
+            template_paths(&paths.template_kind, &paths.def_config)[0],
+            folder.join("page.etlua").to_string_lossy()
+        );
+
+        let paths = args_with(config_path, Some("-"), Some("site"))
+            .load_paths()
+            .unwrap();
+        assert_eq!(paths.build_dir, "site");
+        assert_eq!(paths.json, "-");
+    }
 }
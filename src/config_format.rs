@@ -0,0 +1,98 @@
+use std::path::Path;
+
+use anyhow::Context;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+/// The formats a config file can be written in
+pub(crate) enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// Picks the format based on the extension, files without a known extension are read as json
+    pub(crate) fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|x| x.to_str()) {
+            Some("toml") => Self::Toml,
+            Some("yaml" | "yml") => Self::Yaml,
+            _ => Self::Json,
+        }
+    }
+
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Toml => "toml",
+            Self::Yaml => "yaml",
+        }
+    }
+
+    /// Deserializes `text`, calling `on_unknown_key` with the path of every key that is not part of `T`
+    pub(crate) fn parse<T: serde::de::DeserializeOwned>(
+        self,
+        text: &str,
+        on_unknown_key: impl FnMut(serde_ignored::Path),
+    ) -> Result<T, anyhow::Error> {
+        Ok(match self {
+            Self::Json => {
+                let mut deserializer = serde_json::Deserializer::from_str(text);
+                let value = serde_ignored::deserialize(&mut deserializer, on_unknown_key)?;
+                //only whitespace may follow the config
+                deserializer.end()?;
+                value
+            }
+            Self::Toml => {
+                serde_ignored::deserialize(toml::Deserializer::new(text), on_unknown_key)?
+            }
+            Self::Yaml => serde_ignored::deserialize(
+                serde_yaml::Deserializer::from_str(text),
+                on_unknown_key,
+            )?,
+        })
+    }
+
+    pub(crate) fn to_string(self, value: &impl serde::Serialize) -> Result<String, anyhow::Error> {
+        match self {
+            Self::Json => serde_json::to_string_pretty(value).context("Could not write json"),
+            Self::Toml => toml::to_string_pretty(value).context("Could not write toml"),
+            Self::Yaml => serde_yaml::to_string(value).context("Could not write yaml"),
+        }
+    }
+}
+
+impl std::str::FromStr for ConfigFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "toml" => Ok(Self::Toml),
+            "yaml" => Ok(Self::Yaml),
+            x => Err(anyhow::anyhow!("Unknown config format: {x}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_rejects_trailing_text() {
+        let parse = |text| ConfigFormat::Json.parse::<serde_json::Value>(text, |_| ());
+        assert!(parse("{\"name\": \"api\"}\n").is_ok());
+        assert!(parse("{\"name\": \"api\"} {}").is_err());
+        assert!(parse("{\"name\": \"api\"}}").is_err());
+    }
+
+    #[test]
+    fn format_follows_the_extension() {
+        let format = |path| ConfigFormat::from_path(Path::new(path)).extension();
+        assert_eq!(format("tealr_doc_gen_config.toml"), "toml");
+        assert_eq!(format("tealr_doc_gen_config.yml"), "yaml");
+        assert_eq!(format("tealr_doc_gen_config.yaml"), "yaml");
+        assert_eq!(format("tealr_doc_gen_config.json"), "json");
+        assert_eq!(format("tealr_doc_gen_config"), "json");
+    }
+}
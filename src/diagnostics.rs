@@ -0,0 +1,693 @@
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    fmt::Display,
+    path::{Component, Path},
+    str::FromStr,
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use crate::app::DiagnosticsConfig;
+
+#[derive(
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Debug,
+    serde::Serialize,
+    serde::Deserialize,
+    schemars::JsonSchema,
+)]
+#[serde(rename_all = "kebab-case")]
+/// Every kind of problem tealr_doc_gen can report.
+///
+/// The serialized names are stable and are used to refer to a diagnostic from the outside.
+pub enum DiagnosticCode {
+    /// A type is used somewhere but was never given to the TypeWalker
+    MissingExport,
+    /// A link points to a type that is not known
+    UnknownLink,
+    /// Multiple types share the same name
+    DuplicateType,
+    /// A `teal_lua` snippet contains syntax errors
+    TealSyntaxError,
+    /// A `teal_lua` snippet contains type errors
+    TealTypeError,
+    /// The json was created by a different version of tealr
+    VersionMismatch,
+    /// Reported by a template
+    Template,
+    /// The config file contains unknown keys, names that do not exist or is outdated
+    Config,
+}
+
+impl DiagnosticCode {
+    pub const ALL: [DiagnosticCode; 8] = [
+        DiagnosticCode::MissingExport,
+        DiagnosticCode::UnknownLink,
+        DiagnosticCode::DuplicateType,
+        DiagnosticCode::TealSyntaxError,
+        DiagnosticCode::TealTypeError,
+        DiagnosticCode::VersionMismatch,
+        DiagnosticCode::Template,
+        DiagnosticCode::Config,
+    ];
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiagnosticCode::MissingExport => "missing-export",
+            DiagnosticCode::UnknownLink => "unknown-link",
+            DiagnosticCode::DuplicateType => "duplicate-type",
+            DiagnosticCode::TealSyntaxError => "teal-syntax-error",
+            DiagnosticCode::TealTypeError => "teal-type-error",
+            DiagnosticCode::VersionMismatch => "version-mismatch",
+            DiagnosticCode::Template => "template",
+            DiagnosticCode::Config => "config",
+        }
+    }
+    pub fn description(&self) -> &'static str {
+        match self {
+            DiagnosticCode::MissingExport => "A type is used, but was not added to the TypeWalker",
+            DiagnosticCode::UnknownLink => "A link points to a type that does not exist",
+            DiagnosticCode::DuplicateType => "Multiple types share the same name",
+            DiagnosticCode::TealSyntaxError => "A teal_lua snippet contains syntax errors",
+            DiagnosticCode::TealTypeError => "A teal_lua snippet contains type errors",
+            DiagnosticCode::VersionMismatch => {
+                "The json was made with a different version of tealr than tealr_doc_gen"
+            }
+            DiagnosticCode::Template => "Reported by a template",
+            DiagnosticCode::Config => {
+                "The config file contains unknown keys, names that do not exist or is outdated"
+            }
+        }
+    }
+    pub fn severity(&self) -> Severity {
+        match self {
+            DiagnosticCode::TealSyntaxError | DiagnosticCode::TealTypeError => Severity::Error,
+            DiagnosticCode::MissingExport
+            | DiagnosticCode::UnknownLink
+            | DiagnosticCode::DuplicateType
+            | DiagnosticCode::VersionMismatch
+            | DiagnosticCode::Template
+            | DiagnosticCode::Config => Severity::Warning,
+        }
+    }
+}
+
+impl FromStr for DiagnosticCode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.to_owned()))
+            .map_err(|_| anyhow::anyhow!("Unknown diagnostic code: {s}"))
+    }
+}
+
+impl Display for DiagnosticCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => f.write_str("warning"),
+            Severity::Error => f.write_str("error"),
+        }
+    }
+}
+
+#[derive(
+    Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, serde::Serialize, serde::Deserialize,
+)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+/// Where a diagnostic was found
+pub enum Location {
+    /// The json file containing the type definitions
+    Input { path: String },
+    /// A type, or a member of a type
+    Type {
+        name: String,
+        member: Option<String>,
+    },
+    /// A global instance
+    Global { name: String },
+    /// One of the extra pages
+    ExtraPage { name: String },
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Location::Input { path } => f.write_str(path),
+            Location::Type { name, member: None } => f.write_str(name),
+            Location::Type {
+                name,
+                member: Some(member),
+            } => write!(f, "{name}.{member}"),
+            Location::Global { name } => write!(f, "global {name}"),
+            Location::ExtraPage { name } => write!(f, "page {name}"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Diagnostic {
+    pub code: DiagnosticCode,
+    pub severity: Severity,
+    pub location: Option<Location>,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}[{}]: {}", self.severity, self.code, self.message)?;
+        if let Some(location) = &self.location {
+            write!(f, "\n  --> {location}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+/// How diagnostics are shown at the end of a run
+pub enum MessageFormat {
+    /// Readable text on stderr
+    #[default]
+    Human,
+    /// A json array on stdout
+    Json,
+    /// A SARIF log on stdout
+    Sarif,
+}
+
+impl FromStr for MessageFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Self::Human),
+            "json" => Ok(Self::Json),
+            "sarif" => Ok(Self::Sarif),
+            x => Err(anyhow::anyhow!("Unknown message format: {x}")),
+        }
+    }
+}
+
+impl Location {
+    fn to_sarif(&self) -> serde_json::Value {
+        match self {
+            Location::Input { path } => serde_json::json!({
+                "physicalLocation": {
+                    "artifactLocation": { "uri": path_to_uri(path) }
+                }
+            }),
+            Location::Type { name, member } => serde_json::json!({
+                "logicalLocations": [{
+                    "fullyQualifiedName": self.to_string(),
+                    "name": member.as_ref().unwrap_or(name),
+                    "kind": if member.is_some() { "member" } else { "type" }
+                }]
+            }),
+            Location::Global { name } => serde_json::json!({
+                "logicalLocations": [{
+                    "fullyQualifiedName": name,
+                    "name": name,
+                    "kind": "variable"
+                }]
+            }),
+            Location::ExtraPage { name } => serde_json::json!({
+                "logicalLocations": [{
+                    "fullyQualifiedName": name,
+                    "name": name,
+                    "kind": "module"
+                }]
+            }),
+        }
+    }
+}
+
+/// Turns a path into a URI as SARIF expects it.
+///
+/// Paths inside of the current directory become relative URIs, other absolute paths become `file://` URIs.
+fn path_to_uri(path: &str) -> String {
+    let path = Path::new(path);
+    let path = std::env::current_dir()
+        .ok()
+        .and_then(|cwd| path.strip_prefix(cwd).ok())
+        .unwrap_or(path);
+    let mut absolute = false;
+    let mut parts = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(x) => {
+                absolute = true;
+                parts.push(x.as_os_str().to_string_lossy().into_owned());
+            }
+            Component::RootDir => absolute = true,
+            Component::CurDir => (),
+            Component::ParentDir => parts.push("..".to_owned()),
+            Component::Normal(x) => parts.push(percent_encode(&x.to_string_lossy())),
+        }
+    }
+    if absolute {
+        format!("file:///{}", parts.join("/"))
+    } else {
+        parts.join("/")
+    }
+}
+
+/// Escapes everything that is not allowed in a segment of a URI path
+fn percent_encode(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~!$&'()*+,;=:@".contains(&byte) {
+            encoded.push(char::from(byte));
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+fn to_sarif(diagnostics: &[Diagnostic]) -> serde_json::Value {
+    let rules = DiagnosticCode::ALL
+        .iter()
+        .map(|code| {
+            serde_json::json!({
+                "id": code.as_str(),
+                "shortDescription": { "text": code.description() },
+                "defaultConfiguration": { "level": code.severity().to_string() }
+            })
+        })
+        .collect::<Vec<_>>();
+    let results = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            serde_json::json!({
+                "ruleId": diagnostic.code.as_str(),
+                "ruleIndex": DiagnosticCode::ALL.iter().position(|x| *x == diagnostic.code),
+                "level": diagnostic.severity.to_string(),
+                "message": { "text": diagnostic.message },
+                "locations": diagnostic
+                    .location
+                    .iter()
+                    .map(Location::to_sarif)
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect::<Vec<_>>();
+    serde_json::json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "tealr_doc_gen",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": "https://github.com/lenscas/tealr_doc_gen",
+                    "rules": rules
+                }
+            },
+            "results": results
+        }]
+    })
+}
+
+/// Finds the candidate that is closest to `word`, if any of them is close enough to likely be a typo of it
+pub(crate) fn did_you_mean<'a>(word: &str, candidates: &'a [String]) -> Option<&'a str> {
+    fn edit_distance(a: &str, b: &str) -> usize {
+        let b = b.chars().collect::<Vec<_>>();
+        let mut previous = (0..=b.len()).collect::<Vec<_>>();
+        for (i, a) in a.chars().enumerate() {
+            let mut current = vec![i + 1];
+            for (j, b) in b.iter().enumerate() {
+                let substitution = previous[j] + usize::from(a != *b);
+                current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+            }
+            previous = current;
+        }
+        previous[b.len()]
+    }
+    let max_distance = (word.chars().count() / 3).max(1);
+    candidates
+        .iter()
+        .map(|x| (edit_distance(word, x), x))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, x)| x.as_str())
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+/// A diagnostic as it got reported, together with the key [report_once] deduplicates it by.
+///
+/// These get stored in the build manifest, so pages that are not rendered again can still report what they found.
+pub(crate) struct Recorded {
+    key: Option<String>,
+    diagnostic: Diagnostic,
+}
+
+#[derive(Default)]
+struct CollectorState {
+    recorded: Vec<Recorded>,
+    reported_keys: HashSet<(DiagnosticCode, String, Option<Location>)>,
+}
+
+#[derive(Clone, Default)]
+/// Stores the diagnostics of a run.
+///
+/// Diagnostics go to the collector the reporting thread is scoped to, see [Collector::scope]. Threads that are not scoped to one share a single collector.
+pub(crate) struct Collector(Arc<Mutex<CollectorState>>);
+
+thread_local! {
+    static CURRENT: RefCell<Option<Collector>> = const { RefCell::new(None) };
+}
+
+static SHARED: LazyLock<Collector> = LazyLock::new(Collector::default);
+
+impl Collector {
+    /// Runs `f`, sending every diagnostic reported on this thread in the meantime to this collector
+    pub(crate) fn scope<R>(&self, f: impl FnOnce() -> R) -> R {
+        struct Restore(Option<Collector>);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                let previous = self.0.take();
+                CURRENT.with(|x| *x.borrow_mut() = previous);
+            }
+        }
+        let _restore = Restore(CURRENT.with(|x| x.replace(Some(self.clone()))));
+        f()
+    }
+
+    fn push(&self, key: Option<String>, diagnostic: Diagnostic) {
+        let mut state = self.0.lock().unwrap();
+        //the same problem tends to be found many times in a single place, only one of those is interesting
+        if let Some(key) = &key {
+            let seen = (diagnostic.code, key.clone(), diagnostic.location.clone());
+            if !state.reported_keys.insert(seen) {
+                return;
+            }
+        }
+        state.recorded.push(Recorded { key, diagnostic });
+    }
+
+    /// Adds diagnostics that were recorded somewhere else, like in another collector or in a previous run
+    pub(crate) fn replay(&self, recorded: impl IntoIterator<Item = Recorded>) {
+        for x in recorded {
+            self.push(x.key, x.diagnostic);
+        }
+    }
+
+    /// Takes the diagnostics as they got reported, leaving the collector empty
+    pub(crate) fn take_recorded(&self) -> Vec<Recorded> {
+        let mut state = self.0.lock().unwrap();
+        state.reported_keys.clear();
+        std::mem::take(&mut state.recorded)
+    }
+
+    /// Takes every diagnostic reported so far, leaving the collector empty for the next run.
+    ///
+    /// Pages get rendered in parallel, so the diagnostics are sorted to keep the output the same between runs.
+    /// Only the first of the diagnostics reported through [report_once] with the same code and key is kept.
+    pub(crate) fn take(&self) -> Vec<Diagnostic> {
+        let mut recorded = self.take_recorded();
+        recorded.sort_by(|a, b| {
+            let a = &a.diagnostic;
+            let b = &b.diagnostic;
+            (a.code, &a.location, &a.message).cmp(&(b.code, &b.location, &b.message))
+        });
+        let mut keys = HashSet::new();
+        recorded
+            .into_iter()
+            .filter(|x| match &x.key {
+                Some(key) => keys.insert((x.diagnostic.code, key.clone())),
+                None => true,
+            })
+            .map(|x| x.diagnostic)
+            .collect()
+    }
+}
+
+/// The collector diagnostics reported on this thread go to
+pub(crate) fn current() -> Collector {
+    CURRENT
+        .with(|x| x.borrow().clone())
+        .unwrap_or_else(|| SHARED.clone())
+}
+
+/// Adds a diagnostic to the ones that get shown at the end of the run
+pub(crate) fn report(code: DiagnosticCode, location: Option<Location>, message: impl Into<String>) {
+    current().push(
+        None,
+        Diagnostic {
+            code,
+            severity: code.severity(),
+            location,
+            message: message.into(),
+        },
+    );
+}
+
+/// Same as [report] but only the first diagnostic with the given code and key gets shown.
+pub(crate) fn report_once(
+    code: DiagnosticCode,
+    key: impl Into<String>,
+    location: Option<Location>,
+    message: impl Into<String>,
+) {
+    current().push(
+        Some(key.into()),
+        Diagnostic {
+            code,
+            severity: code.severity(),
+            location,
+            message: message.into(),
+        },
+    );
+}
+
+/// Takes every diagnostic reported so far on this thread, see [Collector::take]
+pub(crate) fn take() -> Vec<Diagnostic> {
+    current().take()
+}
+
+/// Takes the diagnostics of this run, prints the ones that are not allowed and returns an error if any of them are denied.
+///
+/// In strict mode, every diagnostic that is not allowed is denied.
+pub(crate) fn finish(
+    strict: bool,
+    config: &DiagnosticsConfig,
+    format: MessageFormat,
+) -> Result<(), anyhow::Error> {
+    let diagnostics = take()
+        .into_iter()
+        .filter(|x| !config.allow.contains(&x.code))
+        .collect::<Vec<_>>();
+    match format {
+        MessageFormat::Human => print_report(&diagnostics),
+        MessageFormat::Json => println!("{}", serde_json::to_string_pretty(&diagnostics)?),
+        MessageFormat::Sarif => {
+            println!("{}", serde_json::to_string_pretty(&to_sarif(&diagnostics))?)
+        }
+    }
+    let denied = diagnostics
+        .iter()
+        .filter(|x| strict || config.deny.contains(&x.code))
+        .count();
+    if denied > 0 {
+        return Err(anyhow::anyhow!(
+            "Generation failed because of {denied} denied diagnostic(s)"
+        ));
+    }
+    Ok(())
+}
+
+/// Prints the diagnostics to stderr, followed by a summary
+fn print_report(diagnostics: &[Diagnostic]) {
+    for diagnostic in diagnostics {
+        eprintln!("{diagnostic}\n");
+    }
+    let errors = diagnostics
+        .iter()
+        .filter(|x| x.severity == Severity::Error)
+        .count();
+    let warnings = diagnostics.len() - errors;
+    eprintln!("Finished with {warnings} warning(s) and {errors} error(s).");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn type_location(name: &str) -> Option<Location> {
+        Some(Location::Type {
+            name: name.to_owned(),
+            member: None,
+        })
+    }
+
+    fn summary(diagnostics: &[Diagnostic]) -> Vec<String> {
+        diagnostics
+            .iter()
+            .map(|x| format!("{}:{}:{}", x.code, x.location.as_ref().unwrap(), x.message))
+            .collect()
+    }
+
+    #[test]
+    fn sorts_by_code_location_and_message() {
+        let collector = Collector::default();
+        collector.scope(|| {
+            report(DiagnosticCode::UnknownLink, type_location("B"), "a");
+            report(DiagnosticCode::UnknownLink, type_location("A"), "b");
+            report(DiagnosticCode::MissingExport, type_location("C"), "c");
+            report(DiagnosticCode::UnknownLink, type_location("A"), "a");
+        });
+        assert_eq!(
+            summary(&collector.take()),
+            [
+                "missing-export:C:c",
+                "unknown-link:A:a",
+                "unknown-link:A:b",
+                "unknown-link:B:a"
+            ]
+        );
+        assert!(collector.take().is_empty());
+    }
+
+    #[test]
+    fn report_once_keeps_the_first_after_sorting() {
+        let collector = Collector::default();
+        collector.scope(|| {
+            report_once(DiagnosticCode::UnknownLink, "X", type_location("B"), "b");
+            report_once(DiagnosticCode::UnknownLink, "X", type_location("A"), "a");
+            report_once(DiagnosticCode::UnknownLink, "Y", type_location("B"), "c");
+        });
+        assert_eq!(
+            summary(&collector.take()),
+            ["unknown-link:A:a", "unknown-link:B:c"]
+        );
+    }
+
+    #[test]
+    fn scopes_keep_diagnostics_apart() {
+        let outer = Collector::default();
+        let inner = Collector::default();
+        outer.scope(|| {
+            inner.scope(|| report(DiagnosticCode::Template, type_location("A"), "inner"));
+            report(DiagnosticCode::Template, type_location("A"), "outer");
+        });
+        let recorded = inner.take_recorded();
+        assert_eq!(recorded.len(), 1);
+        outer.replay(recorded);
+        assert_eq!(
+            summary(&outer.take()),
+            ["template:A:inner", "template:A:outer"]
+        );
+    }
+
+    fn finish_with(
+        strict: bool,
+        allow: Vec<DiagnosticCode>,
+        deny: Vec<DiagnosticCode>,
+        reported: &[DiagnosticCode],
+    ) -> Result<(), anyhow::Error> {
+        let collector = Collector::default();
+        collector.scope(|| {
+            for code in reported {
+                report(*code, type_location("A"), "problem");
+            }
+            finish(
+                strict,
+                &DiagnosticsConfig { allow, deny },
+                MessageFormat::Json,
+            )
+        })
+    }
+
+    #[test]
+    fn diagnostics_only_fail_the_run_when_denied() {
+        let reported = [DiagnosticCode::UnknownLink, DiagnosticCode::Template];
+        assert!(finish_with(false, vec![], vec![], &reported).is_ok());
+        assert!(finish_with(false, vec![], vec![DiagnosticCode::Template], &reported).is_err());
+        assert!(finish_with(false, vec![], vec![DiagnosticCode::Config], &reported).is_ok());
+    }
+
+    #[test]
+    fn strict_mode_denies_every_diagnostic() {
+        assert!(finish_with(true, vec![], vec![], &[DiagnosticCode::Template]).is_err());
+        assert!(finish_with(true, vec![], vec![], &[]).is_ok());
+    }
+
+    #[test]
+    fn allowed_diagnostics_never_fail_the_run() {
+        let allow = vec![DiagnosticCode::Template];
+        assert!(finish_with(true, allow.clone(), vec![], &[DiagnosticCode::Template]).is_ok());
+        assert!(finish_with(
+            false,
+            allow.clone(),
+            allow.clone(),
+            &[DiagnosticCode::Template]
+        )
+        .is_ok());
+        assert!(finish_with(true, allow, vec![], &[DiagnosticCode::Config]).is_err());
+    }
+
+    #[test]
+    fn finish_leaves_the_collector_empty() {
+        let collector = Collector::default();
+        collector.scope(|| {
+            report(DiagnosticCode::Template, type_location("A"), "problem");
+            assert!(finish(true, &DiagnosticsConfig::default(), MessageFormat::Json).is_err());
+            assert!(finish(true, &DiagnosticsConfig::default(), MessageFormat::Json).is_ok());
+        });
+    }
+
+    #[test]
+    fn relative_paths_become_relative_uris() {
+        assert_eq!(path_to_uri("./types.json"), "types.json");
+        assert_eq!(path_to_uri("docs/my types.json"), "docs/my%20types.json");
+        assert_eq!(path_to_uri("../types#1.json"), "../types%231.json");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn absolute_paths_become_file_uris() {
+        let inside = std::env::current_dir().unwrap().join("types.json");
+        assert_eq!(path_to_uri(&inside.to_string_lossy()), "types.json");
+        assert_eq!(
+            path_to_uri("/tealr_doc_gen_test/types.json"),
+            "file:///tealr_doc_gen_test/types.json"
+        );
+    }
+
+    #[test]
+    fn did_you_mean_suggests_the_closest_key() {
+        let candidates = ["store_in", "name", "is_global", "page_root"]
+            .map(String::from)
+            .to_vec();
+        assert_eq!(did_you_mean("store_inn", &candidates), Some("store_in"));
+        assert_eq!(did_you_mean("is_globl", &candidates), Some("is_global"));
+        assert_eq!(did_you_mean("nane", &candidates), Some("name"));
+        //short keys only allow a single edit
+        assert_eq!(did_you_mean("nm", &candidates), None);
+        assert_eq!(did_you_mean("template", &candidates), None);
+        assert_eq!(did_you_mean("name", &[]), None);
+    }
+
+    #[test]
+    fn did_you_mean_counts_characters_instead_of_bytes() {
+        let candidates = vec!["naïve".to_owned()];
+        assert_eq!(did_you_mean("naive", &candidates), Some("naïve"));
+    }
+}
@@ -13,6 +13,17 @@ pub(crate) fn get_type_name(type_def: &TypeGenerator) -> String {
     )
 }
 
+pub(crate) fn type_signature(ty: &Type) -> String {
+    type_to_name(
+        ty,
+        "",
+        TypeConfig {
+            part_off: TypeIsPartOf::None,
+            known_generics: Default::default(),
+        },
+    )
+}
+
 pub(crate) fn type_should_be_inlined(v: &TypeGenerator) -> bool {
     match v {
         TypeGenerator::Record(x) => x.should_be_inlined,
@@ -0,0 +1,172 @@
+use std::path::{Path, PathBuf};
+
+use tealr::TypeWalker;
+
+use crate::{
+    app::{DiagnosticsConfig, LuaAddon, Paths, SandboxConfig, TemplateKind, TypeDefFile},
+    diagnostics::{Collector, MessageFormat},
+    generation::{run_definition_files, run_from_walker, run_lua_addon},
+};
+
+/// Generates documentation straight from a [TypeWalker], without going through a json and config file.
+///
+/// Every option has the same default as the config file that `tealr_doc_gen gen-self --config` creates.
+///
+/// ```no_run
+/// # fn main() -> anyhow::Result<()> {
+/// let walker = tealr::TypeWalker::new();
+/// tealr_doc_gen::DocGenerator::new("my_api", walker)
+///     .build_dir("target/docs")
+///     .is_global(false)
+///     .generate()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct DocGenerator {
+    paths: Paths,
+    walker: TypeWalker,
+    /// Every generator reports its own diagnostics, so generators used side by side don't fail each other
+    diagnostics: Collector,
+}
+
+impl DocGenerator {
+    /// `name` is the name of the library, used for the definition files and the lua language server addon
+    pub fn new(name: impl Into<String>, walker: TypeWalker) -> Self {
+        Self {
+            paths: Paths {
+                json: String::new(),
+                config_file: None,
+                name: name.into(),
+                root: String::new(),
+                build_dir: "pages".into(),
+                template_kind: TemplateKind::Builtin,
+                def_config: TypeDefFile::default(),
+                is_global: true,
+                lua_addon: Some(LuaAddon::Create {
+                    words: Vec::new(),
+                    files: Vec::new(),
+                    settings: Default::default(),
+                    generic_class_fallback: false,
+                    enum_tables: Default::default(),
+                }),
+                strict: false,
+                diagnostics: DiagnosticsConfig::default(),
+                message_format: MessageFormat::Human,
+                relative_links: false,
+                keep_going: false,
+                sandbox: None,
+            },
+            walker,
+            diagnostics: Collector::default(),
+        }
+    }
+
+    /// Folder to store the documentation in. Same as `store_in` in the config file
+    pub fn build_dir(mut self, build_dir: impl Into<String>) -> Self {
+        self.paths.build_dir = build_dir.into();
+        self
+    }
+
+    /// Sub folder the documentation is hosted under. Same as `page_root` in the config file
+    pub fn page_root(mut self, page_root: impl Into<String>) -> Self {
+        self.paths.root = page_root.into();
+        self
+    }
+
+    /// If the library is directly available in the global scope or has to be loaded first
+    pub fn is_global(mut self, is_global: bool) -> Self {
+        self.paths.is_global = is_global;
+        self
+    }
+
+    /// Template used to generate the html pages
+    pub fn template(mut self, template: TemplateKind) -> Self {
+        self.paths.template_kind = template;
+        self
+    }
+
+    /// Which definition files get generated and how
+    pub fn definition_files(mut self, config: TypeDefFile) -> Self {
+        self.paths.def_config = config;
+        self
+    }
+
+    /// Configuration of the lua language server addon. `None` doesn't generate one
+    pub fn lua_addon(mut self, config: Option<LuaAddon>) -> Self {
+        self.paths.lua_addon = config;
+        self
+    }
+
+    /// Fail when any diagnostic gets reported
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.paths.strict = strict;
+        self
+    }
+
+    /// Which diagnostics are reported and which ones fail the run
+    pub fn diagnostics(mut self, config: DiagnosticsConfig) -> Self {
+        self.paths.diagnostics = config;
+        self
+    }
+
+    /// How diagnostics are printed
+    pub fn message_format(mut self, format: MessageFormat) -> Self {
+        self.paths.message_format = format;
+        self
+    }
+
+    /// Makes every link relative to the page it is on. Same as `relative_links` in the config file
+    pub fn relative_links(mut self, relative_links: bool) -> Self {
+        self.paths.relative_links = relative_links;
+        self
+    }
+
+    /// Keep generating the other files when one of them fails, reporting every failure at the end
+    pub fn keep_going(mut self, keep_going: bool) -> Self {
+        self.paths.keep_going = keep_going;
+        self
+    }
+
+    /// Runs templates and runners in a sandbox. Same as `sandbox` in the config file
+    pub fn sandbox(mut self, config: Option<SandboxConfig>) -> Self {
+        self.paths.sandbox = config;
+        self
+    }
+
+    /// Generates the html pages together with the definition files and lua language server addon they link to.
+    ///
+    /// This is the same as `tealr_doc_gen run`.
+    pub fn generate(self) -> Result<(), anyhow::Error> {
+        let Self {
+            paths,
+            walker,
+            diagnostics,
+        } = self;
+        diagnostics.scope(|| run_from_walker(paths, walker))
+    }
+
+    /// Generates only the definition files, in a `definitions` folder inside of `folder`.
+    ///
+    /// Returns the path of the `definitions` folder.
+    ///
+    /// Definition files that an earlier call for the same library generated in `folder` but this one did not get removed.
+    /// Other files in `folder` are left alone.
+    pub fn generate_definition_files(
+        &self,
+        folder: impl AsRef<Path>,
+    ) -> Result<PathBuf, anyhow::Error> {
+        self.diagnostics
+            .scope(|| run_definition_files(&self.paths, self.walker.clone(), folder.as_ref()))
+    }
+
+    /// Generates only the lua language server addon, as a zip file inside of `folder`.
+    ///
+    /// Returns false if [DocGenerator::lua_addon] turned the addon off.
+    ///
+    /// An addon that an earlier call for the same library generated in `folder` gets removed when this one doesn't generate it.
+    /// Other files in `folder` are left alone.
+    pub fn generate_lua_addon(&self, folder: impl AsRef<Path>) -> Result<bool, anyhow::Error> {
+        self.diagnostics
+            .scope(|| run_lua_addon(&self.paths, self.walker.clone(), folder.as_ref()))
+    }
+}
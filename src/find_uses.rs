@@ -3,13 +3,13 @@ use tealr::{
     TypeWalker,
 };
 
-#[derive(Clone, tealr::mlu::FromToLua, tealr::ToTypename)]
+#[derive(Clone, tealr::mlu::FromToLua, tealr::ToTypename, serde::Serialize)]
 pub struct NameAndSignature {
     name: NameContainer,
     signature: Type,
 }
 
-#[derive(Clone, tealr::mlu::FromToLua, tealr::ToTypename)]
+#[derive(Clone, tealr::mlu::FromToLua, tealr::ToTypename, serde::Serialize)]
 ///Defines how another type uses this type
 pub struct User {
     ///the type that references the current type
@@ -0,0 +1,449 @@
+use std::{
+    collections::BTreeMap,
+    path::{Component, Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::Context;
+
+use crate::diagnostics::Recorded;
+
+use super::staging::link_or_copy;
+
+const MANIFEST_NAME: &str = ".tealr_doc_gen_manifest.json";
+
+/// Name of the manifest of a run that only generates some of the files, like the definition files of a single library.
+///
+/// Every kind of run gets its own manifest, so runs that share a folder don't remove each other's files as stale.
+pub(super) fn partial_manifest_name(kind: &str, library: &str) -> String {
+    let library = library
+        .chars()
+        .map(|x| {
+            if x.is_ascii_alphanumeric() || x == '-' || x == '_' {
+                x
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    format!(".tealr_doc_gen_{kind}_{library}_manifest.json")
+}
+
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+/// Remembers what every generated file was made from, so unchanged files can be skipped in the next run
+struct Manifest {
+    /// Version of tealr_doc_gen that wrote the manifest. Nothing gets reused when it differs
+    version: String,
+    /// Every generated file, relative to the output folder, with the hash of its inputs.
+    ///
+    /// These are the only files tealr_doc_gen is allowed to remove.
+    files: BTreeMap<String, String>,
+    /// The diagnostics found while generating a file, which get reported again while the file is fresh
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    diagnostics: BTreeMap<String, Vec<Recorded>>,
+}
+
+/// Hashes the inputs of a generated file.
+///
+/// FNV-1a is used instead of `DefaultHasher` because the hashes are stored and need to stay the same between builds of tealr_doc_gen.
+pub(super) fn hash(parts: &[&[u8]]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for part in parts {
+        //the length is included so moving bytes from one part to the next changes the hash
+        for byte in (part.len() as u64).to_le_bytes().iter().chain(part.iter()) {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    format!("{hash:016x}")
+}
+
+/// Serializes a value in a way that stays the same between runs.
+///
+/// HashMaps get serialized in a random order, going through a Value sorts their keys.
+pub(super) fn stable_json(value: &impl serde::Serialize) -> Result<String, serde_json::Error> {
+    Ok(serde_json::to_value(value)?.to_string())
+}
+
+pub(super) struct BuildCache {
+    /// Folder the previous run generated its files in
+    previous_path: PathBuf,
+    manifest_name: String,
+    write_path: PathBuf,
+    previous: Manifest,
+    current: Mutex<BTreeMap<String, String>>,
+    current_diagnostics: Mutex<BTreeMap<String, Vec<Recorded>>>,
+    rebuilt: Mutex<Vec<String>>,
+}
+
+impl BuildCache {
+    /// Loads the manifest of the previous run. A missing or unreadable manifest means everything gets generated.
+    pub(super) fn load(write_path: &Path) -> Self {
+        Self::load_from(write_path, write_path)
+    }
+
+    /// Same as [BuildCache::load], using a manifest made by [partial_manifest_name] instead of the one of a full run
+    pub(super) fn load_named(write_path: &Path, manifest_name: String) -> Self {
+        Self::load_manifest(write_path, write_path, manifest_name)
+    }
+
+    /// Same as [BuildCache::load], for a run that writes to a different folder than the previous run did.
+    ///
+    /// Files that are fresh get linked from `previous_path` into `write_path`.
+    pub(super) fn load_from(previous_path: &Path, write_path: &Path) -> Self {
+        Self::load_manifest(previous_path, write_path, MANIFEST_NAME.to_owned())
+    }
+
+    fn load_manifest(previous_path: &Path, write_path: &Path, manifest_name: String) -> Self {
+        let previous = std::fs::read_to_string(previous_path.join(&manifest_name))
+            .ok()
+            .and_then(|x| serde_json::from_str::<Manifest>(&x).ok())
+            .unwrap_or_default();
+        Self {
+            previous_path: previous_path.to_owned(),
+            manifest_name,
+            write_path: write_path.to_owned(),
+            previous,
+            current: Default::default(),
+            current_diagnostics: Default::default(),
+            rebuilt: Default::default(),
+        }
+    }
+
+    /// Returns true if the file at `relative` inside of the output folder got generated by the previous run
+    pub(super) fn owns(&self, relative: &Path) -> bool {
+        let key = relative.to_string_lossy().replace('\\', "/");
+        key == self.manifest_name || self.previous.files.contains_key(&key)
+    }
+
+    /// Makes the file the previous run generated available at `file`, returning false if it doesn't exist anymore
+    fn reuse_previous(&self, key: &str, file: &Path) -> bool {
+        if self.previous_path == self.write_path {
+            return file.exists();
+        }
+        let previous = self.previous_path.join(key);
+        if !previous.is_file() {
+            return false;
+        }
+        if let Some(parent) = file.parent() {
+            if std::fs::create_dir_all(parent).is_err() {
+                return false;
+            }
+        }
+        //fresh files don't get written to, so they can be shared with the previous output
+        let _ = std::fs::remove_file(file);
+        link_or_copy(&previous, file).is_ok()
+    }
+
+    fn key(&self, file: &Path) -> String {
+        file.strip_prefix(&self.write_path)
+            .unwrap_or(file)
+            .to_string_lossy()
+            .replace('\\', "/")
+    }
+
+    /// Returns true if `file` was made from the same inputs in the previous run and still exists.
+    ///
+    /// Files that are fresh are kept in the manifest, other files need to be passed to [BuildCache::built] once they are written.
+    pub(super) fn is_fresh(&self, file: &Path, hash: &str) -> bool {
+        let key = self.key(file);
+        let fresh = self.previous.version == env!("CARGO_PKG_VERSION")
+            && self.previous.files.get(&key).map(String::as_str) == Some(hash)
+            && self.reuse_previous(&key, file);
+        if fresh {
+            self.keep_diagnostics(&key);
+            self.current.lock().unwrap().insert(key, hash.to_owned());
+        }
+        fresh
+    }
+
+    /// The diagnostics that were found while `file` got generated in the previous run
+    pub(super) fn previous_diagnostics(&self, file: &Path) -> Vec<Recorded> {
+        self.previous
+            .diagnostics
+            .get(&self.key(file))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn keep_diagnostics(&self, key: &str) {
+        if let Some(diagnostics) = self.previous.diagnostics.get(key) {
+            self.current_diagnostics
+                .lock()
+                .unwrap()
+                .insert(key.to_owned(), diagnostics.clone());
+        }
+    }
+
+    /// Marks `file` as generated from the inputs with the given hash
+    pub(super) fn built(&self, file: &Path, hash: String) {
+        self.built_with_diagnostics(file, hash, Vec::new());
+    }
+
+    /// Same as [BuildCache::built], also storing the diagnostics found while generating the file
+    pub(super) fn built_with_diagnostics(
+        &self,
+        file: &Path,
+        hash: String,
+        diagnostics: Vec<Recorded>,
+    ) {
+        let key = self.key(file);
+        self.rebuilt.lock().unwrap().push(key.clone());
+        if !diagnostics.is_empty() {
+            self.current_diagnostics
+                .lock()
+                .unwrap()
+                .insert(key.clone(), diagnostics);
+        }
+        self.current.lock().unwrap().insert(key, hash);
+    }
+
+    /// Keeps the entry of the previous run for a file that failed to generate.
+    ///
+    /// The file from the previous run is kept around instead of being removed as stale. As its inputs changed, it gets generated again in the next run.
+    pub(super) fn keep_previous(&self, file: &Path) {
+        let key = self.key(file);
+        if let Some(hash) = self
+            .previous
+            .files
+            .get(&key)
+            .filter(|_| self.reuse_previous(&key, file))
+        {
+            self.keep_diagnostics(&key);
+            self.current.lock().unwrap().insert(key, hash.clone());
+        }
+    }
+
+    /// Removes stale files, stores the manifest for the next run and reports which files got generated or removed
+    pub(super) fn finish(self) -> Result<(), anyhow::Error> {
+        let files = self.current.into_inner().unwrap();
+        let removed = remove_stale_files(
+            &self.previous_path,
+            &self.write_path,
+            &self.previous,
+            &files,
+        );
+        let manifest = Manifest {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            files,
+            diagnostics: self.current_diagnostics.into_inner().unwrap(),
+        };
+        let mut rebuilt = self.rebuilt.into_inner().unwrap();
+        rebuilt.sort();
+        std::fs::write(
+            self.write_path.join(&self.manifest_name),
+            serde_json::to_string_pretty(&manifest)?,
+        )
+        .context("Could not write the build manifest")?;
+        if rebuilt.len() == manifest.files.len() {
+            eprintln!("Rebuilt all {} file(s).", rebuilt.len());
+        } else {
+            eprintln!(
+                "Rebuilt {} of {} file(s).",
+                rebuilt.len(),
+                manifest.files.len()
+            );
+            for file in rebuilt {
+                eprintln!("  {file}");
+            }
+        }
+        if !removed.is_empty() {
+            eprintln!("Removed {} stale file(s).", removed.len());
+            for file in removed {
+                eprintln!("  {file}");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Removes the files the previous run generated but this run did not.
+///
+/// Only files listed in the previous manifest are touched, anything else in the output folder is left alone.
+/// When writing to a different folder than the previous run, stale files are not carried over and only need to be reported.
+fn remove_stale_files(
+    previous_path: &Path,
+    write_path: &Path,
+    previous: &Manifest,
+    current: &BTreeMap<String, String>,
+) -> Vec<String> {
+    let mut removed = Vec::new();
+    for key in previous.files.keys() {
+        if current.contains_key(key) {
+            continue;
+        }
+        //the manifest could have been edited by hand, so make sure it can't point outside of the output folder
+        let path = Path::new(key);
+        if !path.components().all(|x| matches!(x, Component::Normal(_))) {
+            continue;
+        }
+        let previous = previous_path.join(path);
+        let path = write_path.join(path);
+        match std::fs::remove_file(&path) {
+            Ok(()) => removed.push(key.clone()),
+            Err(x) if x.kind() == std::io::ErrorKind::NotFound => {
+                if previous != path && previous.is_file() {
+                    removed.push(key.clone());
+                }
+            }
+            Err(x) => eprintln!("Could not remove stale file {}: {x}", path.display()),
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{
+        diagnostics::{self, Collector, DiagnosticCode},
+        test_util::test_folder,
+    };
+
+    #[test]
+    fn hash_includes_the_boundaries_between_parts() {
+        assert_eq!(hash(&[]), "cbf29ce484222325");
+        assert_eq!(hash(&[b"ab"]), hash(&[b"ab"]));
+        assert_ne!(hash(&[b"ab"]), hash(&[b"a", b"b"]));
+        assert_ne!(hash(&[b"a", b""]), hash(&[b"", b"a"]));
+    }
+
+    #[test]
+    fn stable_json_sorts_map_keys() {
+        let map = ('a'..='z')
+            .rev()
+            .map(|x| (x.to_string(), x as u32))
+            .collect::<HashMap<_, _>>();
+        let json = stable_json(&map).unwrap();
+        assert!(json.starts_with(r#"{"a":97,"b":98,"c":99"#));
+        assert_eq!(json, stable_json(&map.clone()).unwrap());
+    }
+
+    #[test]
+    fn fresh_files_keep_their_diagnostics() {
+        let folder = test_folder("fresh_files_keep_their_diagnostics");
+        let page = folder.join("page.html");
+        std::fs::write(&page, "").unwrap();
+        let collector = Collector::default();
+        collector
+            .scope(|| diagnostics::report(DiagnosticCode::TealTypeError, None, "broken snippet"));
+
+        let cache = BuildCache::load(&folder);
+        assert!(!cache.is_fresh(&page, "hash"));
+        cache.built_with_diagnostics(&page, "hash".to_owned(), collector.take_recorded());
+        cache.finish().unwrap();
+
+        let cache = BuildCache::load(&folder);
+        assert!(!cache.is_fresh(&page, "other hash"));
+        assert!(cache.is_fresh(&page, "hash"));
+        collector.replay(cache.previous_diagnostics(&page));
+        let found = collector.take();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].message, "broken snippet");
+        cache.finish().unwrap();
+
+        //the diagnostics are kept for as long as the file stays fresh
+        let cache = BuildCache::load(&folder);
+        assert!(cache.is_fresh(&page, "hash"));
+        assert_eq!(cache.previous_diagnostics(&page).len(), 1);
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn partial_runs_keep_the_files_of_each_other() {
+        let folder = test_folder("partial_runs_keep_the_files_of_each_other");
+        let definitions = folder.join("definitions/lib.d.tl");
+        let addon = folder.join("lib.zip");
+        std::fs::create_dir_all(definitions.parent().unwrap()).unwrap();
+        for (kind, file) in [("definitions", &definitions), ("lua_addon", &addon)] {
+            std::fs::write(file, "").unwrap();
+            let cache = BuildCache::load_named(&folder, partial_manifest_name(kind, "lib"));
+            cache.built(file, "hash".to_owned());
+            cache.finish().unwrap();
+        }
+        assert!(definitions.is_file());
+        assert!(addon.is_file());
+        assert_eq!(
+            partial_manifest_name("definitions", "my lib/v2"),
+            ".tealr_doc_gen_definitions_my_lib_v2_manifest.json"
+        );
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn only_stale_files_of_the_previous_run_are_removed() {
+        let folder = test_folder("only_stale_files_of_the_previous_run_are_removed");
+        let kept = folder.join("kept.html");
+        let stale = folder.join("types/stale.html");
+        let failed = folder.join("failed.html");
+        let not_generated = folder.join("not_generated.html");
+        std::fs::create_dir_all(stale.parent().unwrap()).unwrap();
+        for file in [&kept, &stale, &failed, &not_generated] {
+            std::fs::write(file, "").unwrap();
+        }
+        let cache = BuildCache::load(&folder);
+        for file in [&kept, &stale, &failed] {
+            cache.built(file, "hash".to_owned());
+        }
+        cache.finish().unwrap();
+
+        let cache = BuildCache::load(&folder);
+        assert!(cache.is_fresh(&kept, "hash"));
+        cache.keep_previous(&failed);
+        cache.finish().unwrap();
+        assert!(kept.is_file());
+        assert!(failed.is_file());
+        assert!(!stale.exists());
+        //files the manifest doesn't list were not generated by tealr_doc_gen
+        assert!(not_generated.is_file());
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn stale_files_outside_of_the_output_folder_are_ignored() {
+        let folder = test_folder("stale_files_outside_of_the_output_folder_are_ignored");
+        let output = folder.join("output");
+        let outside = folder.join("outside.html");
+        std::fs::create_dir_all(&output).unwrap();
+        std::fs::write(&outside, "").unwrap();
+        let previous = Manifest {
+            files: [
+                "../outside.html".to_owned(),
+                outside.to_string_lossy().into_owned(),
+                "./../outside.html".to_owned(),
+            ]
+            .into_iter()
+            .map(|x| (x, "hash".to_owned()))
+            .collect(),
+            ..Default::default()
+        };
+        let removed = remove_stale_files(&output, &output, &previous, &BTreeMap::new());
+        assert!(removed.is_empty(), "{removed:?}");
+        assert!(outside.is_file());
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn stale_files_are_reported_when_writing_somewhere_else() {
+        let folder = test_folder("stale_files_are_reported_when_writing_somewhere_else");
+        let previous_path = folder.join("previous");
+        let write_path = folder.join("staging");
+        std::fs::create_dir_all(&previous_path).unwrap();
+        std::fs::create_dir_all(&write_path).unwrap();
+        std::fs::write(previous_path.join("stale.html"), "").unwrap();
+        let previous = Manifest {
+            files: [("stale.html", "hash"), ("gone.html", "hash")]
+                .into_iter()
+                .map(|(file, hash)| (file.to_owned(), hash.to_owned()))
+                .collect(),
+            ..Default::default()
+        };
+        let removed = remove_stale_files(&previous_path, &write_path, &previous, &BTreeMap::new());
+        //the previous output gets replaced as a whole, so it is left alone
+        assert_eq!(removed, ["stale.html"]);
+        assert!(previous_path.join("stale.html").is_file());
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+}
@@ -1,4 +1,7 @@
-use std::{fs::create_dir_all, path::PathBuf};
+use std::{
+    fs::create_dir_all,
+    path::{Path, PathBuf},
+};
 
 use anyhow::Context;
 use tealr::{
@@ -6,9 +9,19 @@ use tealr::{
     TypeWalker,
 };
 
-use crate::Paths;
+use crate::{
+    app::DefTemplateKind,
+    diagnostics::{self, Collector},
+    Paths,
+};
 
-use super::{shared_globals, shared_types};
+use super::{
+    cache::{self, BuildCache},
+    failures::Failures,
+    sandbox::VmOptions,
+    shared_globals, shared_types,
+    template_errors::map_template_error,
+};
 
 #[derive(Default)]
 struct GlobalsDefFile {
@@ -38,13 +51,99 @@ impl ExportInstances for GlobalsDefFile {
     }
 }
 
+/// Everything that is the same for every definition file that gets generated
+struct DefFileContext<'a> {
+    walker: &'a TypeWalker,
+    runner: String,
+    config: &'a Paths,
+    cache: &'a BuildCache,
+    walker_hash: &'a str,
+    vm: &'a VmOptions,
+}
+
+impl DefFileContext<'_> {
+    /// Generates a single definition file, unless it was already generated from the same inputs
+    fn write_d_file(
+        &self,
+        template: &DefTemplateKind,
+        file_path: &Path,
+    ) -> Result<(), anyhow::Error> {
+        let Self {
+            walker,
+            runner,
+            config,
+            cache,
+            walker_hash,
+            vm,
+        } = self;
+        let (template, template_name) = match template {
+            DefTemplateKind::Teal => (
+                include_str!("../../base_teal_definition_template.etlua").to_string(),
+                "<builtin teal definition template>",
+            ),
+            DefTemplateKind::Custom(x) => (
+                std::fs::read_to_string(x)
+                    .with_context(|| format!("Failed to load custom template: {x}"))?,
+                x.as_str(),
+            ),
+        };
+        let hash = cache::hash(&[
+            walker_hash.as_bytes(),
+            runner.as_bytes(),
+            template.as_bytes(),
+            config.name.as_bytes(),
+            &[config.is_global.into()],
+        ]);
+        if cache.is_fresh(file_path, &hash) {
+            diagnostics::current().replay(cache.previous_diagnostics(file_path));
+            return Ok(());
+        }
+        let lua = vm.create_vm()?;
+        let etlua = include_str!("../../etlua.lua").to_string();
+        let x = GlobalsDefFile {
+            etlua,
+            module: (*walker).clone(),
+            template: template.clone(),
+            is_global: config.is_global,
+            name: config.name.clone(),
+        };
+        mlu::set_global_env(x, &lua)?;
+
+        let file_collector = Collector::default();
+        let document = file_collector.scope(|| {
+            lua.load(runner)
+                .set_name("template_runner")
+                .call::<mlu::mlua::String>(())
+        });
+        let found = file_collector.take_recorded();
+        diagnostics::current().replay(found.clone());
+        let document = document
+            .map_err(|x| map_template_error(&lua, x, &template, template_name))
+            .context("Failed running lua template")?;
+        if let Some(folder) = file_path.parent() {
+            create_dir_all(folder).with_context(|| {
+                format!(
+                    "Could not create directories needed for:{}.",
+                    folder.to_string_lossy()
+                )
+            })?;
+        }
+        std::fs::write(file_path, document.as_bytes())
+            .with_context(|| format!("Could not write file{}", file_path.to_string_lossy()))?;
+        cache.built_with_diagnostics(file_path, hash, found);
+        Ok(())
+    }
+}
+
 pub(crate) fn create_d_file(
     walker: TypeWalker,
     path: PathBuf,
-    name: &str,
     config: &Paths,
+    cache: &BuildCache,
+    failures: &Failures,
+    walker_hash: &str,
+    vm: &VmOptions,
 ) -> Result<PathBuf, anyhow::Error> {
-    let is_global = config.is_global;
     let runner = match &config.def_config.runner {
         crate::app::DefTemplateRunnerKind::Builtin => {
             include_str!("../../base_run_template.lua").to_string()
@@ -53,44 +152,28 @@ pub(crate) fn create_d_file(
             .with_context(|| format!("Failed loading custom runner: {x}"))?,
     };
     let page_path = path.join("definitions");
-    for config in config.def_config.templates.values() {
-        let template = match &config.template {
-            crate::app::DefTemplateKind::Teal => {
-                include_str!("../../base_teal_definition_template.etlua").to_string()
-            }
-            crate::app::DefTemplateKind::Custom(x) => std::fs::read_to_string(x)
-                .with_context(|| format!("Failed to load custom template: {x}"))?,
-        };
-        let lua = unsafe { mlu::mlua::Lua::unsafe_new() };
-        let etlua = include_str!("../../etlua.lua").to_string();
-        let x = GlobalsDefFile {
-            etlua,
-            module: walker.clone(),
-            template,
-            is_global,
-            name: name.to_string(),
-        };
-        mlu::set_global_env(x, &lua)?;
-
-        let document: mlu::mlua::String = lua
-            .load(&runner)
-            .set_name("template_runner")
-            .call(())
-            .context("Failed running lua template")?;
-        create_dir_all(&page_path).with_context(|| {
-            format!(
-                "Could not create directories needed for:{}.",
-                page_path.to_string_lossy()
-            )
-        })?;
-        let extension = if config.extension.starts_with('.') {
-            &config.extension[1..]
+    let context = DefFileContext {
+        walker: &walker,
+        runner,
+        config,
+        cache,
+        walker_hash,
+        vm,
+    };
+    for template_config in config.def_config.templates.values() {
+        let extension = if template_config.extension.starts_with('.') {
+            &template_config.extension[1..]
         } else {
-            &config.extension
+            &template_config.extension
         };
-        let page_path = page_path.join(format!("{name}.{extension}"));
-        std::fs::write(&page_path, document.as_bytes())
-            .with_context(|| format!("Could not write file{}", page_path.to_string_lossy()))?;
+        let file_path = page_path.join(format!("{}.{extension}", config.name));
+        if let Err(x) = context.write_d_file(&template_config.template, &file_path) {
+            cache.keep_previous(&file_path);
+            failures.record(x.context(format!(
+                "Failed generating definition file: {}",
+                file_path.to_string_lossy()
+            )))?;
+        }
     }
 
     Ok(page_path)
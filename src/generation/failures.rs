@@ -0,0 +1,84 @@
+use std::sync::Mutex;
+
+/// Collects the files that could not be generated, so the run can keep going when asked to.
+pub(super) struct Failures {
+    keep_going: bool,
+    errors: Mutex<Vec<anyhow::Error>>,
+}
+
+impl Failures {
+    pub(super) fn new(keep_going: bool) -> Self {
+        Self {
+            keep_going,
+            errors: Default::default(),
+        }
+    }
+
+    /// Stores the error when keeping going, otherwise returns it so the run stops
+    pub(super) fn record(&self, error: anyhow::Error) -> Result<(), anyhow::Error> {
+        if !self.keep_going {
+            return Err(error);
+        }
+        self.errors.lock().unwrap().push(error);
+        Ok(())
+    }
+
+    /// Turns every stored error into a single report
+    pub(super) fn finish(self) -> Result<(), anyhow::Error> {
+        let errors = self.errors.into_inner().unwrap();
+        if errors.is_empty() {
+            return Ok(());
+        }
+        let mut report = format!("Failed generating {} file(s):", errors.len());
+        for (i, error) in errors.iter().enumerate() {
+            //the debug format includes the causes, which contain the lua traceback
+            report += &format!("\n\n{}. {error:?}", i + 1);
+        }
+        Err(anyhow::anyhow!(report))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+
+    use super::*;
+
+    fn failure(page: &str) -> anyhow::Error {
+        Err::<(), _>(anyhow::anyhow!(
+            "[string \"template\"]:1: attempt to index a nil value"
+        ))
+        .with_context(|| format!("Failed rendering page {page}"))
+        .unwrap_err()
+    }
+
+    #[test]
+    fn without_keep_going_the_first_failure_stops_the_run() {
+        let failures = Failures::new(false);
+        let error = failures.record(failure("Foo")).unwrap_err();
+        assert_eq!(error.to_string(), "Failed rendering page Foo");
+        assert!(failures.finish().is_ok());
+    }
+
+    #[test]
+    fn keep_going_reports_every_failure_at_the_end() {
+        let failures = Failures::new(true);
+        assert!(failures.record(failure("Foo")).is_ok());
+        assert!(failures.record(failure("Bar")).is_ok());
+        let report = failures.finish().unwrap_err().to_string();
+        assert!(
+            report.starts_with("Failed generating 2 file(s):"),
+            "{report}"
+        );
+        let foo = report.find("1. Failed rendering page Foo").expect(&report);
+        let bar = report.find("2. Failed rendering page Bar").expect(&report);
+        assert!(foo < bar);
+        //the cause holds the lua error and traceback, so it has to be part of the report
+        assert_eq!(report.matches("attempt to index a nil value").count(), 2);
+    }
+
+    #[test]
+    fn keep_going_without_failures_succeeds() {
+        assert!(Failures::new(true).finish().is_ok());
+    }
+}
@@ -2,7 +2,10 @@ use std::collections::HashSet;
 
 use tealr::TypeWalker;
 
-use crate::render_type::is_type_compatible;
+use crate::{
+    diagnostics::{self, DiagnosticCode, Location},
+    render_type::is_type_compatible,
+};
 
 pub fn warn_about_missing_exports(type_defs: &TypeWalker) {
     let mut missing = find_types_missing_export(type_defs);
@@ -25,29 +28,28 @@ pub fn warn_about_missing_exports(type_defs: &TypeWalker) {
             acc
         });
     for (ty, used_by) in grouped {
-        match used_by.as_slice() {
-            [] => {
-                eprintln!(
-                    "Missing type from export: {}. But no matching use found? This sounds like a bug.",
-                    ty.name
-                );
-            }
-            [x] => {
-                eprintln!("Missing type from export: {}. Used by: {}", ty.name, x);
-            }
-            [x, y @ ..] => {
-                eprintln!(
-                    "Missing type from export: {}. Used by: {} and {} others",
-                    ty.name,
-                    x,
-                    y.len()
-                );
-            }
-        }
+        let message = match used_by.as_slice() {
+            [] => format!(
+                "Missing type from export: {}. But no matching use found? This sounds like a bug.",
+                ty.name
+            ),
+            [x] => format!("Missing type from export: {}. Used by: {}", ty.name, x),
+            [x, y @ ..] => format!(
+                "Missing type from export: {}. Used by: {} and {} others",
+                ty.name,
+                x,
+                y.len()
+            ),
+        };
+        diagnostics::report(
+            DiagnosticCode::MissingExport,
+            used_by.into_iter().next(),
+            message,
+        );
     }
 }
 
-fn find_types_missing_export(walker: &TypeWalker) -> Vec<(tealr::SingleType, String)> {
+fn find_types_missing_export(walker: &TypeWalker) -> Vec<(tealr::SingleType, Location)> {
     let mut missing_types = Vec::new();
     let all_exported_types = walker
         .iter()
@@ -66,7 +68,10 @@ fn find_types_missing_export(walker: &TypeWalker) -> Vec<(tealr::SingleType, Str
                     .map(move |x| {
                         (
                             x.ty.clone(),
-                            format!("{}.{}", name, String::from_utf8_lossy(&x.name)),
+                            Location::Type {
+                                name: name.to_string(),
+                                member: Some(String::from_utf8_lossy(&x.name).to_string()),
+                            },
                         )
                     })
                     .chain(extract_all_function_types_from_record_generator(
@@ -75,7 +80,10 @@ fn find_types_missing_export(walker: &TypeWalker) -> Vec<(tealr::SingleType, Str
                     .chain(record_generator.static_fields.iter().map(|x| {
                         (
                             x.ty.clone(),
-                            format!("{}.{}", name2, String::from_utf8_lossy(&x.name)),
+                            Location::Type {
+                                name: name2.to_string(),
+                                member: Some(String::from_utf8_lossy(&x.name).to_string()),
+                            },
                         )
                     }))
                     .collect::<HashSet<_>>()
@@ -86,10 +94,14 @@ fn find_types_missing_export(walker: &TypeWalker) -> Vec<(tealr::SingleType, Str
         .flat_map(|(x, belongs_to)| {
             extract_all_singular_types_from_type(&x).map(move |v| (v, belongs_to.clone()))
         })
-        .chain(walker.global_instances_off.iter().flat_map(|x| {
-            extract_all_singular_types_from_type(&x.ty).map(move |x| {
-                let name = format!("global.{}", x.name.clone());
-                (x, name)
+        .chain(walker.global_instances_off.iter().flat_map(|global| {
+            extract_all_singular_types_from_type(&global.ty).map(move |x| {
+                (
+                    x,
+                    Location::Global {
+                        name: global.name.to_string(),
+                    },
+                )
             })
         }))
         .filter(|(ty, _)| ty.kind == tealr::KindOfType::External)
@@ -142,7 +154,7 @@ fn extract_all_singular_types_from_type<'a>(
 
 fn extract_all_function_types_from_record_generator(
     record_generator: &tealr::RecordGenerator,
-) -> impl Iterator<Item = (tealr::Type, String)> + '_ {
+) -> impl Iterator<Item = (tealr::Type, Location)> + '_ {
     let name = tealr::type_to_string(&record_generator.ty, false).to_string();
     extract_types_from_exported_functions(&record_generator.functions, name.clone())
         .chain(extract_types_from_exported_functions(
@@ -178,7 +190,7 @@ fn extract_all_function_types_from_record_generator(
 fn extract_types_from_exported_functions(
     exported_functions: &[tealr::ExportedFunction],
     record_name: String,
-) -> impl Iterator<Item = (tealr::Type, String)> + '_ {
+) -> impl Iterator<Item = (tealr::Type, Location)> + '_ {
     exported_functions
         .iter()
         .flat_map(move |x| extract_types_from_exported_function(x, record_name.clone()))
@@ -187,12 +199,11 @@ fn extract_types_from_exported_functions(
 fn extract_types_from_exported_function(
     exported_function: &tealr::ExportedFunction,
     record_name: String,
-) -> impl Iterator<Item = (tealr::Type, String)> + '_ {
-    let full_name = format!(
-        "{}.{}",
-        record_name,
-        String::from_utf8_lossy(&exported_function.name)
-    );
+) -> impl Iterator<Item = (tealr::Type, Location)> + '_ {
+    let full_name = Location::Type {
+        name: record_name,
+        member: Some(String::from_utf8_lossy(&exported_function.name).to_string()),
+    };
     let full_name2 = full_name.clone();
     exported_function
         .params
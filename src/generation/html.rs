@@ -1,7 +1,12 @@
 use std::{
     borrow::Cow,
     collections::HashMap,
+    num::NonZeroUsize,
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
 };
 
 use anyhow::Context;
@@ -12,11 +17,16 @@ use tealr::{
 
 use crate::{
     app::{DefTemplateConfig, DefTemplateKind, TemplateKind},
+    diagnostics::{self, Collector, DiagnosticCode, Location},
     doc_gen::{get_type_name, type_should_be_inlined},
     find_uses::NameAndSignature,
     generation::{
+        cache::{self, BuildCache},
+        failures::Failures,
+        sandbox::{reset_instruction_budget, VmOptions},
         shared_globals, shared_types,
         sidebar::{Members, SideBar},
+        template_errors::map_template_error,
     },
     markdown::MarkdownEvent,
     render_type::{type_to_link_url, SingleTypeNoConsume},
@@ -27,25 +37,53 @@ type OptionalMarkdownEvent = Option<MarkdownEvent>;
 
 tealr::create_union_mlua!(pub enum MarkdownTransformation = MarkdownEventTable | OptionalMarkdownEvent );
 
-#[derive(Clone, FromToLua, ToTypename)]
+#[derive(Clone, FromToLua, ToTypename, serde::Serialize)]
 pub(super) enum TypeOrPage {
     Type(TypeDesc),
     IndexPage(IndexPage),
     CustomPage(CustomPage),
 }
-#[derive(Clone, FromToLua, ToTypename)]
+impl TypeOrPage {
+    /// Where diagnostics found while rendering this page are reported to be
+    pub(super) fn location(&self, library_name: &str) -> Location {
+        match self {
+            TypeOrPage::Type(x) => Location::Type {
+                name: x.type_name.clone(),
+                member: None,
+            },
+            TypeOrPage::IndexPage(_) => Location::Type {
+                name: library_name.to_owned(),
+                member: None,
+            },
+            TypeOrPage::CustomPage(x) => Location::ExtraPage {
+                name: x.name.clone(),
+            },
+        }
+    }
+    /// The message added to an error that happened while rendering this page
+    fn failure_context(&self) -> String {
+        match self {
+            TypeOrPage::Type(x) => format!("Failed while generating file for: {}", x.type_name),
+            TypeOrPage::IndexPage(_) => "Error while generating file for the index page".into(),
+            TypeOrPage::CustomPage(x) => {
+                format!("Error while generating custom page named: {}", x.name)
+            }
+        }
+    }
+}
+#[derive(Clone, FromToLua, ToTypename, serde::Serialize)]
 pub(super) struct TypeDesc {
     pub(super) type_members: TypeGenerator,
     pub(super) type_name: String,
     pub(super) used_by: Vec<crate::find_uses::User>,
 }
-#[derive(Clone, FromToLua, ToTypename)]
+#[derive(Clone, FromToLua, ToTypename, serde::Serialize)]
 pub(super) struct IndexPage {
     pub(super) all_types: Vec<TypeGenerator>,
     pub(super) type_name: String,
     pub(super) type_members: TypeGenerator,
 }
-#[derive(Clone, FromToLua, ToTypename)]
+#[derive(Clone, FromToLua, ToTypename, serde::Serialize)]
 pub(super) struct CustomPage {
     pub(super) name: String,
     pub(super) markdown_content: String,
@@ -54,20 +92,23 @@ pub(super) struct CustomPage {
 #[derive(Clone)]
 pub(super) struct GlobalInstancesDoc {
     pub(super) side_bar: Vec<SideBar>,
+    pub(super) search_index: String,
     pub(super) link_path: PathBuf,
     pub(super) etlua: String,
     pub(super) template: String,
     pub(super) page: TypeOrPage,
-    pub(super) all_types: Option<Vec<TypeGenerator>>,
+    pub(super) all_types: Option<Arc<Vec<TypeGenerator>>>,
     pub(super) globals: Option<Vec<GlobalInstance>>,
     pub(super) def_files: HashMap<String, DefTemplateConfig>,
     pub(super) library_name: String,
     pub(super) definition_files_folder: String,
+    pub(super) definition_files_build_folder: String,
 }
 impl Default for GlobalInstancesDoc {
     fn default() -> Self {
         Self {
             side_bar: Default::default(),
+            search_index: "search-index.js".into(),
             link_path: Default::default(),
             etlua: Default::default(),
             template: Default::default(),
@@ -80,6 +121,7 @@ impl Default for GlobalInstancesDoc {
             def_files: Default::default(),
             library_name: Default::default(),
             definition_files_folder: Default::default(),
+            definition_files_build_folder: Default::default(),
         }
     }
 }
@@ -98,9 +140,13 @@ impl ExportInstances for GlobalInstancesDoc {
         let definition_files_folder = self.definition_files_folder;
 
         instance_collector.add_instance("side_bar_types", move |_| Ok(side_bar))?;
+        instance_collector.document_instance("url of the search index of the documentation");
+        instance_collector.add_instance("search_index_url", |_| Ok(self.search_index))?;
+        instance_collector.document_instance("The page that is being rendered.");
+        instance_collector.document_instance("Multiple pages get rendered using the same lua vm, only this global changes between them.");
         instance_collector.add_instance("page", move |_| Ok(self.page))?;
         instance_collector.add_instance("globals", move |_| Ok(globals))?;
-        let all_types_2 = all_types.clone();
+        let all_types_2 = all_types.as_deref().cloned();
         instance_collector.add_instance("all_types", move |_| Ok(all_types_2))?;
         let link_path2 = link_path.clone();
         let all_types2 = all_types.clone();
@@ -109,10 +155,39 @@ impl ExportInstances for GlobalInstancesDoc {
             let all_types = all_types2;
 
             TypedFunction::from_rust_mut(
-                move |_, ty: SingleTypeNoConsume| {
-                    let all_types = all_types.clone();
-                    let link = link.clone();
-                    Ok(type_to_link_url(ty, &all_types.unwrap_or_default(), link))
+                move |lua, ty: SingleTypeNoConsume| {
+                    let location = lua.app_data_ref::<Location>();
+                    Ok(type_to_link_url(
+                        ty,
+                        all_types.as_deref().map(Vec::as_slice).unwrap_or_default(),
+                        link.clone(),
+                        location.as_deref(),
+                    ))
+                },
+                lua,
+            )
+        })?;
+        instance_collector.document_instance("Reports a problem found while rendering the page.");
+        instance_collector.document_instance(
+            "`code` is one of the diagnostic codes like `teal-syntax-error` or `template`.",
+        );
+        instance_collector.document_instance("`member` is the name of the field, function or global that the problem belongs to, if any.");
+        instance_collector.add_instance("report_diagnostic", move |lua| {
+            TypedFunction::from_rust(
+                move |lua, (code, message, member): (String, String, Option<String>)| {
+                    let code = code
+                        .parse::<DiagnosticCode>()
+                        .map_err(mlu::mlua::Error::external)?;
+                    let location = lua.app_data_ref::<Location>();
+                    let location = match (location.as_deref(), member) {
+                        (Some(Location::Type { name, .. }), Some(member)) => Some(Location::Type {
+                            name: name.clone(),
+                            member: Some(member),
+                        }),
+                        (location, _) => location.cloned(),
+                    };
+                    diagnostics::report(code, location, message);
+                    Ok(())
                 },
                 lua,
             )
@@ -121,31 +196,27 @@ impl ExportInstances for GlobalInstancesDoc {
             let link = link_path;
             TypedFunction::from_rust(
                 move |_, name: String| {
-                    let (name, hash) = name
-                        .find('#')
-                        .map(|x| name.split_at(x))
-                        .unwrap_or((&name, ""));
-                    let is_index = match &all_types {
-                        None => false,
-                        Some(x) => x.iter().any(|x| match x {
-                            TypeGenerator::Record(x) => {
-                                x.should_be_inlined && type_to_string(&x.ty, false) == name
-                            }
-                            TypeGenerator::Enum(_) => false,
-                        }),
-                    };
-                    let name = if is_index { "index" } else { name };
-
-                    Ok(link
-                        .join(name.to_string() + ".html" + hash)
-                        .to_string_lossy()
-                        .to_string())
+                    Ok(create_link_url(
+                        &name,
+                        all_types.as_deref().map(Vec::as_slice),
+                        &link,
+                    ))
                 },
                 lua,
             )
         })?;
+        instance_collector.document_instance(
+            "The folder the definition files can be found in once the documentation is generated.",
+        );
         instance_collector
             .add_instance("definition_file_folder", |_| Ok(definition_files_folder))?;
+        instance_collector.document_instance(
+            "The folder the definition files are in while the documentation is being generated.",
+        );
+        instance_collector.document_instance("Use this to compile code that requires them, `definition_file_folder` to show where they are.");
+        instance_collector.add_instance("definition_file_build_folder", |_| {
+            Ok(self.definition_files_build_folder)
+        })?;
         instance_collector.add_instance("markdown_codeblock_kind_creator", |_| {
             Ok(crate::markdown::MarkdownCodeBlockKindCreator {})
         })?;
@@ -194,25 +265,24 @@ impl ExportInstances for GlobalInstancesDoc {
     }
 }
 
-pub(super) struct CreateGlobalInstanceDocs {
-    pub(super) etlua: String,
+/// The template and the lua code running it, loaded once per run
+pub(super) struct Templates {
+    pub(super) runner: String,
     pub(super) template: String,
-    pub(super) type_name: Cow<'static, str>,
+    /// Where the template came from, used when reporting errors in it
+    pub(super) template_name: String,
+    pub(super) etlua: String,
+    /// How the vms that run the template get created
+    pub(super) vm: VmOptions,
 }
 
-pub(super) fn create_globals_docs(
+pub(super) fn load_templates(
     template_kind: &TemplateKind,
-    type_def: &TypeGenerator,
-    a: impl FnOnce(CreateGlobalInstanceDocs) -> GlobalInstancesDoc,
-) -> Result<(String, GlobalInstancesDoc), anyhow::Error> {
-    let type_name = if type_should_be_inlined(type_def) {
-        "index".into()
-    } else {
-        get_type_name(type_def)
-    };
+    vm: VmOptions,
+) -> Result<Templates, anyhow::Error> {
     let base_template = include_str!("../../base_template.etlua");
     let base_runner = include_str!("../../base_run_template.lua");
-    let (template, template_runner) = match template_kind {
+    let (template, runner) = match template_kind {
         TemplateKind::Builtin => (base_template.to_string(), base_runner.to_string()),
         TemplateKind::FromLua(x) => (
             base_template.to_string(),
@@ -225,29 +295,55 @@ pub(super) fn create_globals_docs(
             base_runner.to_string(),
         ),
     };
-    let etlua = include_str!("../../etlua.lua").to_string();
-    Ok((
-        template_runner,
-        a(CreateGlobalInstanceDocs {
-            etlua,
-            template,
-            type_name: type_name.into(),
-        }),
-    ))
+    let template_name = match template_kind {
+        TemplateKind::FromTemplate(x) => x.clone(),
+        TemplateKind::Builtin | TemplateKind::FromLua(_) => "<builtin template>".to_string(),
+    };
+    Ok(Templates {
+        runner,
+        template,
+        template_name,
+        etlua: include_str!("../../etlua.lua").to_string(),
+        vm,
+    })
 }
 
-pub(super) fn run_and_write(
-    write_path: &Path,
-    template_runner: &str,
-    instance_setter: GlobalInstancesDoc,
-) -> Result<(), anyhow::Error> {
-    let lua = unsafe { mlu::mlua::Lua::unsafe_new() };
-    let page_path: PathBuf = match &instance_setter.page {
+/// The name of the page a type gets documented on
+pub(super) fn page_name(type_def: &TypeGenerator) -> Cow<'static, str> {
+    if type_should_be_inlined(type_def) {
+        "index".into()
+    } else {
+        get_type_name(type_def)
+    }
+}
+
+/// The url `create_link` gives to the page of `name`, which may end in a `#fragment`.
+///
+/// Types that are inlined into the index page link to it instead.
+fn create_link_url(name: &str, all_types: Option<&[TypeGenerator]>, link_path: &Path) -> String {
+    let (name, hash) = name
+        .find('#')
+        .map(|x| name.split_at(x))
+        .unwrap_or((name, ""));
+    let is_index = all_types.unwrap_or_default().iter().any(|x| match x {
+        TypeGenerator::Record(x) => x.should_be_inlined && type_to_string(&x.ty, false) == name,
+        TypeGenerator::Enum(_) => false,
+    });
+    let name = if is_index { "index" } else { name };
+    link_path
+        .join(name.to_string() + ".html" + hash)
+        .to_string_lossy()
+        .to_string()
+}
+
+fn page_path(page: &TypeOrPage, all_types: &[TypeGenerator], write_path: &Path) -> PathBuf {
+    match page {
         TypeOrPage::Type(x) => match x.type_members.type_name() {
             tealr::Type::Single(single_type) => type_to_link_url(
                 single_type.to_owned(),
-                instance_setter.all_types.as_deref().unwrap_or_default(),
+                all_types,
                 write_path.to_owned(),
+                None,
             )
             .unwrap_or_else(|| write_path.join(sanitize_filename::sanitize(&x.type_name))),
             tealr::Type::Function(_)
@@ -262,23 +358,143 @@ pub(super) fn run_and_write(
         TypeOrPage::IndexPage(x) => write_path.join(sanitize_filename::sanitize(&x.type_name)),
         TypeOrPage::CustomPage(x) => write_path.join(sanitize_filename::sanitize(&x.name)),
     }
-    .with_extension("html");
-    mlu::set_global_env(instance_setter, &lua).context("Failed while setting globals")?;
+    .with_extension("html")
+}
 
-    let document: mlu::mlua::String = lua
-        .load(template_runner)
-        .set_name("template_runner")
-        .call(())
-        .context("Failed while running template")?;
-    let as_bytes = document.as_bytes();
-    let mut minify_cfg = minify_html::Cfg::spec_compliant();
-    minify_cfg.minify_css = true;
-    //there are currently bugs when minifying js (https://github.com/wilsonzlin/minify-js/issues/15)
-    minify_cfg.minify_js = false;
-    let minified = minify_html::minify(&as_bytes, &minify_cfg);
-    std::fs::write(&page_path, minified)
-        .with_context(|| format!("Could not write to {page_path:?}"))?;
-    Ok(())
+/// A lua vm with every global needed to render pages already set.
+///
+/// Only `page` changes between pages, so the vm and the compiled template get reused.
+struct PageRenderer<'a> {
+    lua: mlu::mlua::Lua,
+    runner: mlu::mlua::Function,
+    templates: &'a Templates,
+    library_name: String,
+}
+
+impl<'a> PageRenderer<'a> {
+    fn new(templates: &'a Templates, globals: GlobalInstancesDoc) -> Result<Self, anyhow::Error> {
+        let lua = templates.vm.create_vm()?;
+        let library_name = globals.library_name.clone();
+        mlu::set_global_env(globals, &lua).context("Failed while setting globals")?;
+        let runner = lua
+            .load(&templates.runner)
+            .set_name("template_runner")
+            .into_function()
+            .context("Failed while loading template runner")?;
+        Ok(Self {
+            lua,
+            runner,
+            templates,
+            library_name,
+        })
+    }
+
+    fn render(&self, page_path: &Path, page: TypeOrPage) -> Result<(), anyhow::Error> {
+        self.lua.set_app_data(page.location(&self.library_name));
+        reset_instruction_budget(&self.lua);
+        self.lua
+            .globals()
+            .set("page", page)
+            .context("Failed while setting page")?;
+
+        let document: mlu::mlua::String = self
+            .runner
+            .call(())
+            .map_err(|x| {
+                map_template_error(
+                    &self.lua,
+                    x,
+                    &self.templates.template,
+                    &self.templates.template_name,
+                )
+            })
+            .context("Failed while running template")?;
+        let as_bytes = document.as_bytes();
+        let mut minify_cfg = minify_html::Cfg::spec_compliant();
+        minify_cfg.minify_css = true;
+        //there are currently bugs when minifying js (https://github.com/wilsonzlin/minify-js/issues/15)
+        minify_cfg.minify_js = false;
+        let minified = minify_html::minify(&as_bytes, &minify_cfg);
+        std::fs::write(page_path, minified)
+            .with_context(|| format!("Could not write to {page_path:?}"))?;
+        Ok(())
+    }
+}
+
+/// Renders every page that changed since the last run and writes them to `write_path`.
+///
+/// The pages are spread over a worker per available core, each with its own lua vm.
+pub(super) fn render_pages(
+    write_path: &Path,
+    templates: &Templates,
+    globals: GlobalInstancesDoc,
+    pages: Vec<TypeOrPage>,
+    cache: &BuildCache,
+    failures: &Failures,
+    shared_hash: &str,
+) -> Result<(), anyhow::Error> {
+    let workers = std::thread::available_parallelism()
+        .map(NonZeroUsize::get)
+        .unwrap_or(1)
+        .min(pages.len())
+        .max(1);
+    let all_types = globals.all_types.clone().unwrap_or_default();
+    let pages = Mutex::new(pages.into_iter());
+    let failed = AtomicBool::new(false);
+    //the workers don't report into the collector of this thread by themselves
+    let collector = diagnostics::current();
+    let work = || -> Result<(), anyhow::Error> {
+        //the vm only gets created once a page needs to be rendered, so runs where nothing changed stay fast
+        let mut renderer = None;
+        while !failed.load(Ordering::SeqCst) {
+            let Some(page) = pages.lock().unwrap().next() else {
+                break;
+            };
+            let page_path = page_path(&page, &all_types, write_path);
+            let hash = cache::hash(&[
+                shared_hash.as_bytes(),
+                cache::stable_json(&page)?.as_bytes(),
+            ]);
+            if cache.is_fresh(&page_path, &hash) {
+                //the page is not rendered again, so what was found while rendering it last time is reported instead
+                collector.replay(cache.previous_diagnostics(&page_path));
+                continue;
+            }
+            if renderer.is_none() {
+                renderer = Some(PageRenderer::new(templates, globals.clone())?);
+            }
+            let page_renderer = renderer.as_ref().expect("renderer was just created");
+            let context = page.failure_context();
+            let page_collector = Collector::default();
+            let res =
+                page_collector.scope(|| page_renderer.render(&page_path, page).context(context));
+            let found = page_collector.take_recorded();
+            collector.replay(found.clone());
+            match res {
+                Ok(()) => cache.built_with_diagnostics(&page_path, hash, found),
+                Err(x) => {
+                    //the failed page can leave globals half set, which should not leak into the next page
+                    renderer = None;
+                    cache.keep_previous(&page_path);
+                    failures.record(x)?;
+                }
+            }
+        }
+        Ok(())
+    };
+    std::thread::scope(|scope| {
+        let handles = (0..workers)
+            .map(|_| scope.spawn(|| work().inspect_err(|_| failed.store(true, Ordering::SeqCst))))
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect::<Result<(), _>>()
+    })
 }
 
 pub fn generate_self_doc() -> Result<TypeWalker, anyhow::Error> {
@@ -311,3 +527,56 @@ pub fn generate_self_doc() -> Result<TypeWalker, anyhow::Error> {
         .document_global_instance::<GlobalInstancesDoc>()
         .context("failed to generate global instance")
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tealr::RecordGenerator;
+
+    use super::*;
+
+    fn record(name: &str, should_be_inlined: bool) -> TypeGenerator {
+        let mut record = serde_json::to_value(RecordGenerator::new::<RecordGenerator>(false))
+            .expect("records can be serialized");
+        record["ty"] = json!({ "Single": { "name": name, "kind": "External", "generics": [] } });
+        record["should_be_inlined"] = json!(should_be_inlined);
+        TypeGenerator::Record(Box::new(
+            serde_json::from_value(record).expect("a valid record"),
+        ))
+    }
+
+    #[test]
+    fn links_without_a_link_path_are_relative() {
+        let link_path = PathBuf::new();
+        assert_eq!(create_link_url("Foo", None, &link_path), "Foo.html");
+        assert_eq!(
+            create_link_url("Foo#method.get", None, &link_path),
+            "Foo.html#method.get"
+        );
+    }
+
+    #[test]
+    fn links_start_at_the_link_path() {
+        let link_path = Path::new("/").join("docs");
+        assert_eq!(create_link_url("Foo", None, &link_path), "/docs/Foo.html");
+        assert_eq!(
+            create_link_url("Foo#field.x", None, &link_path),
+            "/docs/Foo.html#field.x"
+        );
+    }
+
+    #[test]
+    fn inlined_types_link_to_the_index() {
+        let all_types = [record("Lib", true), record("Foo", false)];
+        for link_path in [PathBuf::new(), Path::new("/").join("docs")] {
+            assert_eq!(
+                create_link_url("Lib#function.new", Some(&all_types), &link_path),
+                link_path.join("index.html#function.new").to_string_lossy()
+            );
+            assert_eq!(
+                create_link_url("Foo", Some(&all_types), &link_path),
+                link_path.join("Foo.html").to_string_lossy()
+            );
+        }
+    }
+}
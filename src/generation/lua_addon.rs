@@ -1,15 +1,23 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
 
 use anyhow::Context;
 use serde::Serialize;
 use std::io::Write;
 use tealr::{
-    ExportedFunction, FunctionParam, FunctionRepresentation, MapRepresentation, RecordGenerator,
-    Type, TypeWalker,
+    ExportedFunction, FunctionParam, FunctionRepresentation, MacroExpr, MapRepresentation,
+    RecordGenerator, Type, TypeWalker,
 };
 use zip::write::SimpleFileOptions;
 
-use crate::app::LuaAddon;
+use crate::{
+    app::LuaAddon,
+    diagnostics::{self, DiagnosticCode},
+};
+
+use super::cache::{self, BuildCache};
 
 #[derive(Serialize)]
 struct LuaAddonConfig {
@@ -19,25 +27,80 @@ struct LuaAddonConfig {
     settings: HashMap<String, serde_json::Value>,
 }
 
+/// Options that change how the types are written to the addon
+#[derive(Clone, Copy, Serialize)]
+struct EmitOptions<'a> {
+    /// Declare the generics of classes as `any` aliases, for lua-language-server versions without generic classes
+    generic_class_fallback: bool,
+    /// The enums that are also exposed as a table, with the lua path of that table
+    enum_tables: &'a BTreeMap<String, String>,
+}
+
+impl EmitOptions<'_> {
+    /// Returns true if an enum table gets written to `path`, which means nothing else should be written there
+    fn is_enum_table(&self, path: &str) -> bool {
+        self.enum_tables.values().any(|x| x == path)
+    }
+}
+
+/// Reports the enums in `enum_tables` that are not part of the api, as no table gets written for them
+fn check_enum_tables(type_defs: &TypeWalker, enum_tables: &BTreeMap<String, String>) {
+    let enums = type_defs
+        .given_types
+        .iter()
+        .filter_map(|x| match x {
+            tealr::TypeGenerator::Enum(x) => Some(class_table_name(&x.ty, false)),
+            tealr::TypeGenerator::Record(_) => None,
+        })
+        .collect::<Vec<_>>();
+    for name in enum_tables.keys().filter(|x| !enums.contains(x)) {
+        let message = match diagnostics::did_you_mean(name, &enums) {
+            Some(x) => {
+                format!("`{name}` in `lua_addon.enum_tables` is not an enum. Did you mean `{x}`?")
+            }
+            None => format!("`{name}` in `lua_addon.enum_tables` is not an enum. It is ignored"),
+        };
+        diagnostics::report_once(
+            DiagnosticCode::Config,
+            format!("enum_tables.{name}"),
+            None,
+            message,
+        );
+    }
+}
+
 pub fn create_lua_addon(
     addon_config: LuaAddon,
     type_defs: TypeWalker,
     mut path: PathBuf,
     is_global: bool,
     library_name: &str,
+    cache: &BuildCache,
+    walker_hash: &str,
 ) -> anyhow::Result<bool> {
-    let config = match addon_config {
+    let (config, generic_class_fallback, enum_tables) = match addon_config {
         LuaAddon::False => return Ok(false),
         LuaAddon::Create {
             words,
             files,
             settings,
-        } => LuaAddonConfig {
-            name: library_name.to_owned(),
-            words,
-            files,
-            settings,
-        },
+            generic_class_fallback,
+            enum_tables,
+        } => (
+            LuaAddonConfig {
+                name: library_name.to_owned(),
+                words,
+                files,
+                settings,
+            },
+            generic_class_fallback,
+            enum_tables,
+        ),
+    };
+    check_enum_tables(&type_defs, &enum_tables);
+    let options = EmitOptions {
+        generic_class_fallback,
+        enum_tables: &enum_tables,
     };
     let zip_name = {
         let mut x = String::new();
@@ -46,6 +109,15 @@ pub fn create_lua_addon(
         x
     };
     path.push(&zip_name);
+    let hash = cache::hash(&[
+        walker_hash.as_bytes(),
+        cache::stable_json(&config)?.as_bytes(),
+        cache::stable_json(&options)?.as_bytes(),
+        &[is_global.into()],
+    ]);
+    if cache.is_fresh(&path, &hash) {
+        return Ok(true);
+    }
     let x = std::fs::File::create(&path).context("Could not create zipfile for lua addon")?;
     let mut x = zip::write::ZipWriter::new(x);
     x.start_file("plugin.json", SimpleFileOptions::default())?;
@@ -62,19 +134,21 @@ pub fn create_lua_addon(
         SimpleFileOptions::default(),
     )
     .context("Could not create library.lua file")?;
-    let res = to_lua(&config.name, type_defs, is_global);
+    let res = to_lua(&config.name, type_defs, is_global, options);
     x.write(res.as_bytes())
         .context("Failed writing library definition to library.lua")?;
     x.flush()
         .context("Could not write lua language server addon to disk")?;
     drop(x);
+    cache.built(&path, hash);
     Ok(true)
 }
 
-fn to_lua(name: &str, type_defs: TypeWalker, is_global: bool) -> String {
+fn to_lua(name: &str, type_defs: TypeWalker, is_global: bool, options: EmitOptions) -> String {
     let mut file = String::with_capacity(type_defs.given_types.len());
     file.push_str("---@meta\n\n");
 
+    let mut base_docs = String::new();
     let mut base_fields = String::with_capacity(type_defs.given_types.len());
     let mut base_methods = String::with_capacity(type_defs.given_types.len());
 
@@ -82,19 +156,31 @@ fn to_lua(name: &str, type_defs: TypeWalker, is_global: bool) -> String {
     let mut class_fields = String::new();
     let mut class_methods = String::new();
 
+    //enum tables can be stored inside of other tables, so they are written once every table exists
+    let mut enum_tables = String::new();
+
     let mut class_name = String::new();
     for ty in type_defs.given_types {
         match ty {
             tealr::TypeGenerator::Record(x) => {
                 if x.should_be_inlined {
-                    write_record_as_class(&mut base_methods, &mut base_fields, "", name, &x, false)
+                    write_docs(&mut base_docs, &x.type_doc);
+                    write_record_as_class(
+                        &mut base_methods,
+                        &mut base_fields,
+                        "",
+                        name,
+                        &x,
+                        false,
+                        options,
+                    )
                 } else {
                     class_fields.clear();
                     class_methods.clear();
                     class_name.clear();
                     //class_name.push_str(name);
                     //class_name.push('.');
-                    class_name.push_str(&type_to_name(&x.ty, "", TypeIsPartOf::None));
+                    class_name.push_str(&class_table_name(&x.ty, options.generic_class_fallback));
                     write_record_as_class(
                         &mut class_methods,
                         &mut class_fields,
@@ -102,29 +188,49 @@ fn to_lua(name: &str, type_defs: TypeWalker, is_global: bool) -> String {
                         &class_name,
                         &x,
                         true,
+                        options,
                     );
                     classes += &class_fields;
                     classes += &class_methods;
                 }
             }
             tealr::TypeGenerator::Enum(x) => {
+                let enum_name = class_table_name(&x.ty, options.generic_class_fallback);
+                if let Some(path) = options.enum_tables.get(&enum_name) {
+                    write_docs(&mut enum_tables, &x.type_doc);
+                    enum_tables.push_str("---@enum ");
+                    enum_tables.push_str(&enum_name);
+                    enum_tables.push('\n');
+                    enum_tables.push_str(path);
+                    enum_tables.push_str(" = {\n");
+                    for variant in x.variants {
+                        let variant = String::from_utf8_lossy(&variant);
+                        enum_tables.push_str("    ");
+                        write_table_key(&mut enum_tables, &variant);
+                        enum_tables.push_str(" = ");
+                        enum_tables.push_str(&lua_string(&variant));
+                        enum_tables.push_str(",\n");
+                    }
+                    enum_tables.push_str("}\n");
+                    continue;
+                }
+                write_docs(&mut classes, &x.type_doc);
                 classes.push_str("---@alias ");
                 //classes.push_str(name);
                 //classes.push('.');
-                classes.push_str(&type_to_name(&x.ty, "", TypeIsPartOf::None));
+                classes.push_str(&type_to_name(&x.ty, "", TypeIsPartOf::None, options));
                 classes.push('\n');
+                //tealr doesn't store documentation per variant, so the variants can't get a `# description`
                 for variant in x.variants {
-                    classes.push_str("---|'\"");
-                    classes.push_str(&String::from_utf8_lossy(&variant));
-                    classes.push_str("\"'\n");
+                    classes.push_str("---|");
+                    classes.push_str(&lua_string(&String::from_utf8_lossy(&variant)));
+                    classes.push('\n');
                 }
             }
         }
     }
-    let mut write_function_to = String::new();
     for global in type_defs.global_instances_off {
         if let Type::Function(x) = &global.ty {
-            write_function_to.clear();
             write_function(
                 #[allow(deprecated)]
                 &ExportedFunction {
@@ -134,13 +240,16 @@ fn to_lua(name: &str, type_defs: TypeWalker, is_global: bool) -> String {
                     is_meta_method: false,
                 },
                 "",
+                &[],
                 "",
-                &mut write_function_to,
+                Some(&*global.doc),
                 &mut classes,
+                options,
             )
-        } else {
+        } else if !options.is_enum_table(&global.name) {
+            write_docs(&mut classes, &global.doc);
             classes.push_str("---@type ");
-            classes.push_str(&type_to_name(&global.ty, "", TypeIsPartOf::None));
+            classes.push_str(&type_to_name(&global.ty, "", TypeIsPartOf::None, options));
             classes.push('\n');
             classes.push_str(&global.name);
             classes.push_str(" = nil");
@@ -148,6 +257,7 @@ fn to_lua(name: &str, type_defs: TypeWalker, is_global: bool) -> String {
         }
     }
     file.push_str(&base_fields);
+    file.push_str(&base_docs);
     if !is_global {
         file.push_str("local ");
     }
@@ -155,6 +265,7 @@ fn to_lua(name: &str, type_defs: TypeWalker, is_global: bool) -> String {
     file.push_str(" = {}\n");
     file.push_str(&base_methods);
     file.push_str(&classes);
+    file.push_str(&enum_tables);
     file.push_str("\n return ");
     file.push_str(name);
     file
@@ -167,23 +278,38 @@ fn write_record_as_class(
     class_name: &str,
     generator: &RecordGenerator,
     write_class: bool,
+    options: EmitOptions,
 ) {
-    fields.reserve(generator.fields.len());
+    fields.reserve(generator.fields.len() + generator.static_fields.len());
     methods.reserve(generator.functions.len() + generator.methods.len());
 
+    let class_generics = match &generator.ty {
+        Type::Single(ty) if write_class => ty
+            .generics
+            .iter()
+            .map(|generic| type_to_name(generic, "", TypeIsPartOf::None, options))
+            .collect(),
+        _ => Vec::new(),
+    };
     if write_class {
-        if let Type::Single(ty) = &generator.ty {
-            ty.generics.iter().for_each(|generic| {
+        if options.generic_class_fallback {
+            for generic in &class_generics {
                 fields.push_str("---@diagnostic disable-next-line: duplicate-doc-alias\n");
                 fields.push_str("---@alias ");
-                fields.push_str(&type_to_name(generic, "", TypeIsPartOf::None));
+                fields.push_str(generic);
                 fields.push_str(
                     " any Temporary workaround for lls not having support for generic classes\n",
                 );
-            })
+            }
         }
+        write_docs(fields, &generator.type_doc);
         fields.push_str("---@class ");
         fields.push_str(class_name);
+        if !options.generic_class_fallback && !class_generics.is_empty() {
+            fields.push('<');
+            fields.push_str(&class_generics.join(", "));
+            fields.push('>');
+        }
         if generator.is_user_data || !generator.implements.is_empty() {
             fields.push(':');
         }
@@ -197,32 +323,42 @@ fn write_record_as_class(
             &generator
                 .implements
                 .iter()
-                .map(|v| type_to_name(v, "", TypeIsPartOf::None))
+                .map(|v| type_to_name(v, "", TypeIsPartOf::None, options))
                 .collect::<Vec<_>>()
                 .join(" , "),
         );
         fields.push('\n');
     }
+    //lua-language-server has no concept of static fields, so they are written the same as normal fields
+    //fields that hold an enum table are written together with that table
+    let all_fields = generator
+        .fields
+        .iter()
+        .chain(&generator.static_fields)
+        .filter(|field| {
+            !options.is_enum_table(&format!(
+                "{class_name}.{}",
+                String::from_utf8_lossy(&field.name)
+            ))
+        });
     if write_class {
-        for field in &generator.fields {
+        for field in all_fields {
+            if let Some(x) = generator.documentation.get(&field.name) {
+                write_docs(fields, x);
+            }
             fields.push_str("---@field ");
             fields.push_str(&String::from_utf8_lossy(&field.name));
             fields.push(' ');
-            fields.push_str(&type_to_name(&field.ty, base, TypeIsPartOf::None));
-            if let Some(x) = generator.documentation.get(&field.name) {
-                fields.push(' ');
-                fields.push_str(&x.replace('\n', "<br>"));
-            }
+            fields.push_str(&type_to_name(&field.ty, base, TypeIsPartOf::None, options));
             fields.push('\n');
         }
     } else {
-        for field in &generator.fields {
-            methods.push_str("---@type ");
-            methods.push_str(&type_to_name(&field.ty, base, TypeIsPartOf::None));
+        for field in all_fields {
             if let Some(x) = generator.documentation.get(&field.name) {
-                methods.push(' ');
-                methods.push_str(&x.replace('\n', "<br>"));
+                write_docs(methods, x);
             }
+            methods.push_str("---@type ");
+            methods.push_str(&type_to_name(&field.ty, base, TypeIsPartOf::None, options));
             methods.push('\n');
             methods.push_str(class_name);
             methods.push('.');
@@ -232,31 +368,151 @@ fn write_record_as_class(
     }
     if write_class {
         methods.push_str("local ");
-        methods.push_str(&type_to_name(&generator.ty, "", TypeIsPartOf::None));
+        methods.push_str(class_name);
         methods.push_str(" = {}\n");
     }
-    let mut function_written_out = String::new();
+    //the generics of the class are already known to lua-language-server, declaring them again would turn them into new ones
+    let class_generics: &[String] = if options.generic_class_fallback {
+        &[]
+    } else {
+        class_generics.as_slice()
+    };
+    let macro_expressions = generator
+        .macro_expressions
+        .iter()
+        .map(macro_as_function)
+        .collect::<Vec<_>>();
+    let mut meta_functions = Vec::new();
+    for function in generator
+        .meta_function
+        .iter()
+        .chain(generator.meta_function_mut.iter())
+        .chain(generator.meta_method.iter())
+        .chain(generator.meta_method_mut.iter())
+    {
+        //operators can only be declared on a class
+        let operator = if write_class {
+            write_operator(function, class_generics, base, options)
+        } else {
+            None
+        };
+        match operator {
+            Some(operator) => {
+                if let Some(docs) = generator.documentation.get(&function.name) {
+                    write_docs(fields, docs);
+                }
+                fields.push_str(&operator);
+                //the operator only has the return type of a call with multiple parameters, the function has the parameters as well
+                if String::from_utf8_lossy(&function.name) == "__call" && function.params.len() > 2
+                {
+                    meta_functions.push(function);
+                }
+            }
+            None => meta_functions.push(function),
+        }
+    }
     generator
         .functions
         .iter()
         .chain(generator.mut_functions.iter())
         .chain(generator.methods.iter())
         .chain(generator.mut_methods.iter())
-        .chain(generator.meta_function.iter())
-        .chain(generator.meta_function_mut.iter())
-        .chain(generator.meta_method.iter())
-        .chain(generator.meta_method_mut.iter())
+        .chain(meta_functions)
+        .chain(macro_expressions.iter())
         .for_each(|function| {
             write_function(
                 function,
                 class_name,
+                class_generics,
                 base,
-                &mut function_written_out,
+                generator
+                    .documentation
+                    .get(&function.name)
+                    .map(String::as_str),
                 methods,
+                options,
             )
         });
 }
 
+/// Writes a metamethod as an `---@operator` annotation, so lua-language-server knows the type of `a + b`, `#a`, `a()` and so on.
+///
+/// Returns `None` for metamethods that have no operator, as well as for those with generics of their own as an operator can't declare them
+fn write_operator(
+    function: &ExportedFunction,
+    class_generics: &[String],
+    base: &str,
+    options: EmitOptions,
+) -> Option<String> {
+    const UNARY: &[&str] = &["unm", "bnot", "len"];
+    const BINARY: &[&str] = &[
+        "add", "sub", "mul", "div", "mod", "pow", "idiv", "band", "bor", "bxor", "shl", "shr",
+        "concat",
+    ];
+    let name = String::from_utf8_lossy(&function.name);
+    let operator = name.strip_prefix("__")?;
+    if function
+        .get_generics()
+        .iter()
+        .any(|generic| !class_generics.contains(&generic.to_string()))
+    {
+        return None;
+    }
+    //the first parameter is the value the operator is used on
+    let input = if UNARY.contains(&operator) {
+        None
+    } else if BINARY.contains(&operator) {
+        match function.params.as_slice() {
+            [_, other] => Some(other),
+            _ => return None,
+        }
+    } else if operator == "call" {
+        //lua-language-server only takes a single parameter type, so calls with more parameters only get their return type
+        match function.params.as_slice() {
+            [_, param] => Some(param),
+            _ => None,
+        }
+    } else {
+        return None;
+    };
+    let mut written = String::from("---@operator ");
+    written.push_str(operator);
+    if let Some(input) = input {
+        written.push('(');
+        written.push_str(&type_to_name(
+            &input.ty,
+            base,
+            TypeIsPartOf::FunctionParameter,
+            options,
+        ));
+        written.push(')');
+    }
+    written.push_str(": ");
+    //an operator results in a single value, so only the first one that gets returned is used
+    match function.returns.first() {
+        Some(returned) => written.push_str(&type_to_name(
+            returned,
+            base,
+            TypeIsPartOf::FunctionReturn,
+            options,
+        )),
+        None => written.push_str("nil"),
+    }
+    written.push('\n');
+    Some(written)
+}
+
+/// Macro expressions get expanded by the teal compiler, from lua they can only be called like a function
+#[allow(deprecated)]
+fn macro_as_function(macro_expr: &MacroExpr) -> ExportedFunction {
+    ExportedFunction {
+        name: macro_expr.name.clone(),
+        params: macro_expr.signature.params.clone(),
+        returns: macro_expr.signature.returns.clone(),
+        is_meta_method: macro_expr.is_meta_method,
+    }
+}
+
 fn get_param_name(param: &FunctionParam, key: usize) -> String {
     if let Type::Variadic(_) = param.ty {
         String::from("...")
@@ -269,22 +525,51 @@ fn get_param_name(param: &FunctionParam, key: usize) -> String {
     }
 }
 
+/// Writes markdown documentation as `---` comments, which lua-language-server shows when hovering over what follows them.
+///
+/// Lines starting with `@` would be read as annotations, so the `@` gets escaped. Markdown shows `\@` as `@`.
+fn write_docs(to: &mut String, docs: &str) {
+    for line in docs.trim_end().lines() {
+        to.push_str("---");
+        if !line.is_empty() {
+            to.push(' ');
+            let text = line.trim_start();
+            if text.starts_with('@') {
+                to.push_str(&line[..line.len() - text.len()]);
+                to.push('\\');
+                to.push_str(text);
+            } else {
+                to.push_str(line);
+            }
+        }
+        to.push('\n');
+    }
+}
+
 fn write_function(
     function: &ExportedFunction,
     class_name: &str,
+    class_generics: &[String],
     base: &str,
-    function_written_out: &mut String,
+    docs: Option<&str>,
     methods: &mut String,
+    options: EmitOptions,
 ) {
-    let generics = function.get_generics();
-
-    for generic in generics {
+    if let Some(docs) = docs {
+        write_docs(methods, docs);
+    }
+    let mut declared = Vec::new();
+    for generic in function.get_generics() {
+        let generic = generic.to_string();
+        if class_generics.contains(&generic) || declared.contains(&generic) {
+            continue;
+        }
         methods.push_str("---@generic ");
-        methods.push_str(&generic.to_string());
+        methods.push_str(&generic);
         methods.push('\n');
+        declared.push(generic);
     }
-    function_written_out.clear();
-    function_written_out.push_str("function ");
+    let mut function_written_out = String::from("function ");
     if !class_name.is_empty() {
         function_written_out.push_str(class_name);
         function_written_out.push('.');
@@ -304,6 +589,7 @@ fn write_function(
                 &param.ty,
                 base,
                 TypeIsPartOf::FunctionParameter,
+                options,
             ));
 
             methods.push('\n')
@@ -313,10 +599,74 @@ fn write_function(
     function_written_out.push_str(") end\n");
     for returned in &function.returns {
         methods.push_str("---@return ");
-        methods.push_str(&type_to_name(returned, base, TypeIsPartOf::FunctionReturn));
+        methods.push_str(&type_to_name(
+            returned,
+            base,
+            TypeIsPartOf::FunctionReturn,
+            options,
+        ));
         methods.push('\n');
     }
-    methods.push_str(function_written_out);
+    methods.push_str(&function_written_out);
+}
+
+/// Writes a key of a table constructor, using the `["key"]` syntax when the key is not a valid name
+fn write_table_key(to: &mut String, key: &str) {
+    const KEYWORDS: &[&str] = &[
+        "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if",
+        "in", "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+    ];
+    let is_name = key
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !KEYWORDS.contains(&key);
+    if is_name {
+        to.push_str(key);
+    } else {
+        to.push('[');
+        to.push_str(&lua_string(key));
+        to.push(']');
+    }
+}
+
+/// Writes `text` as a lua string literal.
+///
+/// Rust's escapes like `\u{7f}` don't exist in lua, so control characters are written as decimal escapes instead
+fn lua_string(text: &str) -> String {
+    let mut written = String::with_capacity(text.len() + 2);
+    written.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => written.push_str("\\\""),
+            '\\' => written.push_str("\\\\"),
+            '\n' => written.push_str("\\n"),
+            '\r' => written.push_str("\\r"),
+            '\t' => written.push_str("\\t"),
+            //the 3 digits stop a digit that follows from becoming part of the escape
+            c if c.is_ascii_control() => written.push_str(&format!("\\{:03}", c as u32)),
+            c => written.push(c),
+        }
+    }
+    written.push('"');
+    written
+}
+
+/// Name of the table a class is stored in, which can't contain the generics of the class
+fn class_table_name(ty: &Type, generic_class_fallback: bool) -> String {
+    match ty {
+        Type::Single(x) => x.name.to_string(),
+        x => type_to_name(
+            x,
+            "",
+            TypeIsPartOf::None,
+            EmitOptions {
+                generic_class_fallback,
+                enum_tables: &BTreeMap::new(),
+            },
+        ),
+    }
 }
 
 #[derive(PartialEq, Eq)]
@@ -326,7 +676,7 @@ enum TypeIsPartOf {
     FunctionReturn,
 }
 
-fn type_to_name(ty: &Type, base: &str, part_off: TypeIsPartOf) -> String {
+fn type_to_name(ty: &Type, base: &str, part_off: TypeIsPartOf, options: EmitOptions) -> String {
     match ty {
         Type::Function(FunctionRepresentation { params, returns }) => {
             let mut name = "fun(".to_string();
@@ -335,7 +685,8 @@ fn type_to_name(ty: &Type, base: &str, part_off: TypeIsPartOf) -> String {
                     let param_name = get_param_name(param, key);
                     name += &param_name;
                     name += ":";
-                    name += &type_to_name(&param.ty, base, TypeIsPartOf::FunctionParameter);
+                    name +=
+                        &type_to_name(&param.ty, base, TypeIsPartOf::FunctionParameter, options);
                     name += ",";
                 }
                 name.pop();
@@ -343,7 +694,7 @@ fn type_to_name(ty: &Type, base: &str, part_off: TypeIsPartOf) -> String {
             name += "):";
             if !returns.is_empty() {
                 for returned in returns {
-                    name += &type_to_name(returned, base, TypeIsPartOf::FunctionReturn);
+                    name += &type_to_name(returned, base, TypeIsPartOf::FunctionReturn, options);
                     name += ",";
                 }
                 name.pop();
@@ -353,7 +704,18 @@ fn type_to_name(ty: &Type, base: &str, part_off: TypeIsPartOf) -> String {
             name
         }
         Type::Single(x) => {
-            let name = x.name.to_string();
+            let mut name = x.name.to_string();
+            if !options.generic_class_fallback && !x.generics.is_empty() {
+                name.push('<');
+                name.push_str(
+                    &x.generics
+                        .iter()
+                        .map(|v| type_to_name(v, base, TypeIsPartOf::None, options))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                );
+                name.push('>');
+            }
             let full_name = if x.kind.is_external() {
                 let mut string = String::new();
                 if !base.is_empty() {
@@ -374,18 +736,21 @@ fn type_to_name(ty: &Type, base: &str, part_off: TypeIsPartOf) -> String {
                 .unwrap_or(full_name)
         }
         Type::Array(x) => {
-            let x = type_to_name(x, base, TypeIsPartOf::None);
+            let x = type_to_name(x, base, TypeIsPartOf::None, options);
             format! {"({})[]",x}
         }
         Type::Map(MapRepresentation { key, value }) => {
-            let key = type_to_name(key, base, TypeIsPartOf::None);
-            let value = type_to_name(value, base, TypeIsPartOf::None);
+            let key = type_to_name(key, base, TypeIsPartOf::None, options);
+            let value = type_to_name(value, base, TypeIsPartOf::None, options);
             format!("{{ [{}]: {}}}", key, value)
         }
         Type::Or(x) => {
             let mut name = String::with_capacity(x.len() * 2 + 4);
             name.push_str("( ");
-            for part in x.iter().map(|v| type_to_name(v, base, TypeIsPartOf::None)) {
+            for part in x
+                .iter()
+                .map(|v| type_to_name(v, base, TypeIsPartOf::None, options))
+            {
                 name += &part;
                 name.push('|');
             }
@@ -395,7 +760,10 @@ fn type_to_name(ty: &Type, base: &str, part_off: TypeIsPartOf) -> String {
         }
         Type::Tuple(x) => {
             let mut name = String::with_capacity(x.len() * 2);
-            for part in x.iter().map(|v| type_to_name(v, base, TypeIsPartOf::None)) {
+            for part in x
+                .iter()
+                .map(|v| type_to_name(v, base, TypeIsPartOf::None, options))
+            {
                 name += &part;
                 name.push(',');
             }
@@ -403,7 +771,7 @@ fn type_to_name(ty: &Type, base: &str, part_off: TypeIsPartOf) -> String {
             name
         }
         Type::Variadic(x) => {
-            let mut name = type_to_name(x, base, TypeIsPartOf::None);
+            let mut name = type_to_name(x, base, TypeIsPartOf::None, options);
             if part_off == TypeIsPartOf::FunctionReturn {
                 name += " ...";
             }
@@ -411,3 +779,421 @@ fn type_to_name(ty: &Type, base: &str, part_off: TypeIsPartOf) -> String {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use tealr::TypeGenerator;
+
+    use super::*;
+    use crate::diagnostics::Collector;
+
+    fn ty(name: &str, kind: &str, generics: Vec<Value>) -> Value {
+        json!({ "Single": { "name": name, "kind": kind, "generics": generics } })
+    }
+
+    fn external(name: &str) -> Value {
+        ty(name, "External", Vec::new())
+    }
+
+    fn builtin(name: &str) -> Value {
+        ty(name, "Builtin", Vec::new())
+    }
+
+    fn generic(name: &str) -> Value {
+        ty(name, "Generic", Vec::new())
+    }
+
+    fn function(name: &str, params: &[Value], returns: &[Value]) -> Value {
+        let params = params
+            .iter()
+            .map(|x| json!({ "param_name": null, "ty": x }))
+            .collect::<Vec<_>>();
+        json!({ "name": name, "params": params, "returns": returns, "is_meta_method": false })
+    }
+
+    /// A record of the type `ty`, with the members in `members` instead of the default ones
+    fn record(ty: Value, members: Value) -> TypeGenerator {
+        let mut record = serde_json::to_value(RecordGenerator::new::<RecordGenerator>(false))
+            .expect("records can be serialized");
+        record["ty"] = ty;
+        record["should_be_inlined"] = json!(false);
+        for (key, value) in members.as_object().expect("members are an object") {
+            record[key] = value.clone();
+        }
+        TypeGenerator::Record(Box::new(
+            serde_json::from_value(record).expect("a valid record"),
+        ))
+    }
+
+    /// An enum of the type `ty`, with the given variants
+    fn enumeration(ty: Value, variants: &[&str], type_doc: &str) -> TypeGenerator {
+        TypeGenerator::Enum(
+            serde_json::from_value(json!({ "ty": ty, "variants": variants, "type_doc": type_doc }))
+                .expect("a valid enum"),
+        )
+    }
+
+    fn emit(given_types: Vec<TypeGenerator>) -> String {
+        emit_with(
+            given_types,
+            EmitOptions {
+                generic_class_fallback: false,
+                enum_tables: &BTreeMap::new(),
+            },
+        )
+    }
+
+    fn emit_with(given_types: Vec<TypeGenerator>, options: EmitOptions) -> String {
+        let mut walker = TypeWalker::new();
+        walker.given_types = given_types;
+        to_lua("lib", walker, true, options)
+    }
+
+    /// Asserts that the lines appear in this order, with nothing in between
+    fn assert_lines(lua: &str, expected: &[&str]) {
+        let lines = lua.lines().collect::<Vec<_>>();
+        assert!(
+            lines.windows(expected.len()).any(|x| x == expected),
+            "expected\n{}\nin\n{lua}",
+            expected.join("\n")
+        );
+    }
+
+    #[test]
+    fn docs_are_written_before_what_they_document() {
+        let lua = emit(vec![record(
+            external("Foo"),
+            json!({
+                "type_doc": "A foo.\n\nSecond paragraph",
+                "functions": [function("new", &[], &[external("Foo")])],
+                "methods": [function("get", &[external("Foo")], &[builtin("integer")])],
+                "documentation": {
+                    "new": "Creates a foo",
+                    "get": "Gets the value.\n@param is not an annotation\n  @return neither"
+                }
+            }),
+        )]);
+        assert_lines(
+            &lua,
+            &["--- A foo.", "---", "--- Second paragraph", "---@class Foo"],
+        );
+        assert_lines(
+            &lua,
+            &[
+                "--- Creates a foo",
+                "---@return Foo",
+                "function Foo.new() end",
+            ],
+        );
+        assert_lines(
+            &lua,
+            &[
+                "--- Gets the value.",
+                "--- \\@param is not an annotation",
+                "---   \\@return neither",
+                "---@param Param1 Foo",
+            ],
+        );
+    }
+
+    #[test]
+    fn static_fields_are_written_like_fields() {
+        let members = json!({
+            "fields": [{ "name": "value", "ty": builtin("integer") }],
+            "static_fields": [{ "name": "DEFAULT", "ty": external("Foo") }],
+            "documentation": { "DEFAULT": "The default foo" }
+        });
+        let lua = emit(vec![record(external("Foo"), members.clone())]);
+        assert_lines(
+            &lua,
+            &[
+                "---@class Foo",
+                "---@field value integer",
+                "--- The default foo",
+                "---@field DEFAULT Foo",
+            ],
+        );
+
+        //inlined records become fields of the library itself
+        let mut inlined = record(external("Globals"), members);
+        if let TypeGenerator::Record(x) = &mut inlined {
+            x.should_be_inlined = true;
+        }
+        let lua = emit(vec![inlined]);
+        assert_lines(
+            &lua,
+            &["--- The default foo", "---@type Foo", "lib.DEFAULT = nil"],
+        );
+    }
+
+    #[test]
+    fn macro_expressions_are_written_as_functions() {
+        let lua = emit(vec![record(
+            external("Foo"),
+            json!({
+                "macro_expressions": [{
+                    "name": "assert_ok",
+                    "signature": { "params": [{ "param_name": "value", "ty": builtin("boolean") }], "returns": [] },
+                    "is_meta_method": false
+                }],
+                "documentation": { "assert_ok": "Expanded by teal" }
+            }),
+        )]);
+        assert_lines(
+            &lua,
+            &[
+                "--- Expanded by teal",
+                "---@param value boolean",
+                "function Foo.assert_ok(value) end",
+            ],
+        );
+    }
+
+    fn generic_class() -> TypeGenerator {
+        let foo = ty("Foo", "External", vec![generic("T")]);
+        let mapper = json!({ "Function": {
+            "params": [{ "param_name": null, "ty": generic("T") }],
+            "returns": [generic("U")]
+        } });
+        record(
+            foo.clone(),
+            json!({
+                "methods": [
+                    function("get", &[foo.clone()], &[generic("T")]),
+                    function("map", &[foo, mapper], &[ty("Foo", "External", vec![generic("U")])])
+                ]
+            }),
+        )
+    }
+
+    #[test]
+    fn generic_classes_declare_their_generics_once() {
+        let lua = emit(vec![generic_class()]);
+        assert_lines(&lua, &["---@class Foo<T>", "local Foo = {}"]);
+        assert_lines(
+            &lua,
+            &[
+                "---@param Param1 Foo<T>",
+                "---@return T",
+                "function Foo.get(Param1) end",
+            ],
+        );
+        assert!(!lua.contains("---@generic T"), "{lua}");
+        assert_eq!(lua.matches("---@generic U\n").count(), 1, "{lua}");
+        assert_lines(
+            &lua,
+            &["---@return Foo<U>", "function Foo.map(Param1,Param2) end"],
+        );
+    }
+
+    #[test]
+    fn generic_class_fallback_uses_aliases() {
+        let options = EmitOptions {
+            generic_class_fallback: true,
+            enum_tables: &BTreeMap::new(),
+        };
+        let lua = emit_with(vec![generic_class()], options);
+        assert!(lua.contains("---@alias T any"), "{lua}");
+        assert_lines(&lua, &["---@class Foo", "local Foo = {}"]);
+        //without generic classes every function declares the generics it uses
+        assert_lines(&lua, &["---@generic T", "---@param Param1 Foo"]);
+        assert!(!lua.contains('<'), "{lua}");
+    }
+
+    #[test]
+    fn metamethods_become_operators() {
+        let foo = external("Foo");
+        let number = builtin("number");
+        let lua = emit(vec![record(
+            foo.clone(),
+            json!({
+                "meta_method": [
+                    function("__add", &[foo.clone(), number.clone()], &[foo.clone()]),
+                    function("__len", &[foo.clone()], &[builtin("integer")]),
+                    function("__call", &[foo.clone(), number.clone()], &[builtin("string")]),
+                    function("__tostring", &[foo.clone()], &[builtin("string")]),
+                    function("__add", &[foo.clone()], &[foo.clone()]),
+                ],
+                "documentation": { "__len": "Amount of items" }
+            }),
+        )]);
+        assert_lines(
+            &lua,
+            &[
+                "---@class Foo",
+                "---@operator add(number): Foo",
+                "--- Amount of items",
+                "---@operator len: integer",
+                "---@operator call(number): string",
+                "local Foo = {}",
+            ],
+        );
+        //metamethods without an operator, or with parameters that don't fit one, stay functions
+        assert!(lua.contains("function Foo.__tostring(Param1) end"), "{lua}");
+        assert!(lua.contains("function Foo.__add(Param1) end"), "{lua}");
+        assert!(!lua.contains("function Foo.__call"), "{lua}");
+    }
+
+    #[test]
+    fn calls_with_multiple_parameters_are_also_functions() {
+        let foo = external("Foo");
+        let lua = emit(vec![record(
+            foo.clone(),
+            json!({
+                "meta_method": [function(
+                    "__call",
+                    &[foo, builtin("number"), builtin("string")],
+                    &[builtin("boolean")]
+                )]
+            }),
+        )]);
+        assert_lines(&lua, &["---@class Foo", "---@operator call: boolean"]);
+        assert_lines(
+            &lua,
+            &[
+                "---@param Param1 Foo",
+                "---@param Param2 number",
+                "---@param Param3 string",
+                "---@return boolean",
+                "function Foo.__call(Param1,Param2,Param3) end",
+            ],
+        );
+    }
+
+    #[test]
+    fn enum_tables_are_written_where_the_api_exposes_them() {
+        let enum_tables = BTreeMap::from([("Color".to_owned(), "Foo.Color".to_owned())]);
+        let options = EmitOptions {
+            generic_class_fallback: false,
+            enum_tables: &enum_tables,
+        };
+        let lua = emit_with(
+            vec![
+                enumeration(external("Color"), &["Red", "Dark_red2"], "A color"),
+                enumeration(external("Size"), &["Big"], ""),
+                record(
+                    external("Foo"),
+                    json!({
+                        "static_fields": [
+                            { "name": "Color", "ty": external("Color") },
+                            { "name": "size", "ty": external("Size") }
+                        ]
+                    }),
+                ),
+            ],
+            options,
+        );
+        assert_lines(
+            &lua,
+            &[
+                "--- A color",
+                "---@enum Color",
+                "Foo.Color = {",
+                "    Red = \"Red\",",
+                "    Dark_red2 = \"Dark_red2\",",
+                "}",
+            ],
+        );
+        //the table can only be filled once the table it is stored in exists
+        assert!(
+            lua.find("local Foo = {}") < lua.find("Foo.Color = {"),
+            "{lua}"
+        );
+        //the field holding the table is written by the table itself
+        assert!(!lua.contains("---@field Color"), "{lua}");
+        assert!(lua.contains("---@field size Size"), "{lua}");
+        assert_lines(&lua, &["---@alias Size", "---|\"Big\""]);
+        assert!(!lua.contains("---@alias Color"), "{lua}");
+    }
+
+    #[test]
+    fn alias_variants_are_escaped() {
+        let lua = emit(vec![enumeration(
+            external("Quote"),
+            &["plain", "it's \"quoted\"", "back\\slash\nnewline"],
+            "",
+        )]);
+        assert_lines(
+            &lua,
+            &[
+                "---@alias Quote",
+                "---|\"plain\"",
+                "---|\"it's \\\"quoted\\\"\"",
+                "---|\"back\\\\slash\\nnewline\"",
+            ],
+        );
+    }
+
+    #[test]
+    fn enum_table_keys_are_quoted_and_escaped() {
+        let enum_tables = BTreeMap::from([("Keys".to_owned(), "Keys".to_owned())]);
+        let options = EmitOptions {
+            generic_class_fallback: false,
+            enum_tables: &enum_tables,
+        };
+        let mut walker = TypeWalker::new();
+        walker.given_types = vec![enumeration(
+            external("Keys"),
+            &[
+                "end",
+                "nil",
+                "two words",
+                "1st",
+                "say \"hi\"\\now",
+                "tab\t\u{7f}1",
+            ],
+            "",
+        )];
+        walker.global_instances_off = serde_json::from_value(json!([
+            { "name": "Keys", "ty": external("Keys"), "doc": "" }
+        ]))
+        .expect("valid globals");
+        let lua = to_lua("lib", walker, true, options);
+        assert_lines(
+            &lua,
+            &[
+                "---@enum Keys",
+                "Keys = {",
+                "    [\"end\"] = \"end\",",
+                "    [\"nil\"] = \"nil\",",
+                "    [\"two words\"] = \"two words\",",
+                "    [\"1st\"] = \"1st\",",
+                "    [\"say \\\"hi\\\"\\\\now\"] = \"say \\\"hi\\\"\\\\now\",",
+                "    [\"tab\\t\\1271\"] = \"tab\\t\\1271\",",
+                "}",
+            ],
+        );
+        //the global is the enum table, so it isn't declared a second time
+        assert!(!lua.contains("Keys = nil"), "{lua}");
+    }
+
+    #[test]
+    fn unknown_enum_tables_are_reported() {
+        let mut walker = TypeWalker::new();
+        walker.given_types = vec![enumeration(external("Color"), &["Red"], "")];
+        let enum_tables = BTreeMap::from([
+            ("Color".to_owned(), "Color".to_owned()),
+            ("Colour".to_owned(), "Colour".to_owned()),
+            ("Shape".to_owned(), "Shape".to_owned()),
+        ]);
+        let collector = Collector::default();
+        collector.scope(|| check_enum_tables(&walker, &enum_tables));
+        let found = collector.take();
+        assert_eq!(found.len(), 2, "{found:?}");
+        assert!(found.iter().all(|x| x.code == DiagnosticCode::Config));
+        let messages = found.iter().map(|x| &*x.message).collect::<Vec<_>>();
+        assert!(
+            messages
+                .iter()
+                .any(|x| x.contains("`Colour`") && x.contains("Did you mean `Color`?")),
+            "{messages:?}"
+        );
+        assert!(
+            messages
+                .iter()
+                .any(|x| x.contains("`Shape`") && x.ends_with("It is ignored")),
+            "{messages:?}"
+        );
+    }
+}
@@ -5,15 +5,21 @@ use tealr::{
 
 use crate::render_type::{find_all_generics, render_type, RenderOptions};
 
+mod cache;
 mod definition_file;
+mod failures;
 mod generate_warnings;
 mod html;
 mod lua_addon;
 mod run;
+mod sandbox;
+mod search_index;
 mod sidebar;
+mod staging;
+mod template_errors;
 pub(crate) use definition_file::generate_self_def;
 pub(crate) use html::generate_self_doc;
-pub(crate) use run::{run_from_walker, run_template};
+pub(crate) use run::{run_definition_files, run_from_walker, run_lua_addon, run_template};
 
 fn shared_types(walker: TypeWalker) -> TypeWalker {
     walker
@@ -1,6 +1,8 @@
 use std::{
     fs::{create_dir_all, read_to_string},
-    path::Path,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::Arc,
 };
 
 use anyhow::Context;
@@ -8,35 +10,94 @@ use tealr::{RecordGenerator, TypeGenerator, TypeWalker};
 
 use crate::{
     app::{DefTemplateConfig, DefTemplateKind, LuaAddon},
+    diagnostics::{self, DiagnosticCode, Location},
     Paths,
 };
 
 use super::{
+    cache::{self, BuildCache},
     definition_file::create_d_file,
+    failures::Failures,
     generate_warnings::warn_about_missing_exports,
     html::{
-        create_globals_docs, run_and_write, CustomPage, GlobalInstancesDoc, IndexPage, TypeDesc,
-        TypeOrPage,
+        load_templates, page_name, render_pages, CustomPage, GlobalInstancesDoc, IndexPage,
+        TypeDesc, TypeOrPage,
     },
     lua_addon::create_lua_addon,
+    sandbox::VmOptions,
+    search_index::{
+        generate_search_index, search_index_script, SEARCH_INDEX_JSON, SEARCH_INDEX_SCRIPT,
+    },
     sidebar::generate_sidebar_data,
+    staging::Staging,
 };
 
-pub(crate) fn run_from_walker(
+pub(crate) fn run_from_walker(paths: Paths, type_defs: TypeWalker) -> Result<(), anyhow::Error> {
+    let strict = paths.strict;
+    let diagnostics_config = paths.diagnostics.clone();
+    let message_format = paths.message_format;
+    let target = Path::new(&paths.build_dir).join(&paths.root);
+    let failures = Failures::new(paths.keep_going);
+    let inputs = paths
+        .config_file
+        .iter()
+        .cloned()
+        .chain([PathBuf::from(&paths.json)])
+        .collect::<Vec<_>>();
+    let res = Staging::create(&target, &inputs).and_then(|staging| {
+        match generate(paths, type_defs, &staging, &target, &failures) {
+            Ok(()) => Ok(staging),
+            Err(x) => {
+                staging.discard();
+                Err(x)
+            }
+        }
+    });
+    let diagnostics_res = diagnostics::finish(strict, &diagnostics_config, message_format);
+    //denied diagnostics also count as a failed run, so they keep the previous documentation as well.
+    //Pages that failed while keeping going do not, the pages that did get generated are kept
+    let res = match (res, diagnostics_res) {
+        (Ok(staging), Ok(())) => staging.commit(),
+        (Ok(staging), Err(x)) => {
+            staging.discard();
+            Err(x)
+        }
+        (Err(x), _) => Err(x),
+    };
+    res.and(failures.finish())
+}
+
+/// Generates everything into the staging folder. `target` is where it ends up once the run succeeded
+fn generate(
     mut paths: Paths,
     type_defs: TypeWalker,
+    staging: &Staging,
+    target: &Path,
+    failures: &Failures,
 ) -> Result<(), anyhow::Error> {
     warn_about_missing_exports(&type_defs);
-    let write_path = Path::new(&paths.build_dir).join(&paths.root);
-    create_dir_all(&write_path)?;
-    let definition_file_storage =
-        create_d_file(type_defs.clone(), write_path.clone(), &paths.name, &paths)?;
+    let write_path = staging.path().to_owned();
+    let cache = BuildCache::load_from(staging.target(), &write_path);
+    staging.carry_over(|x| cache.owns(x))?;
+    let walker_hash = cache::hash(&[cache::stable_json(&type_defs)?.as_bytes()]);
+    let vm = VmOptions::new(paths.sandbox.clone(), &write_path)?;
+    let definition_file_storage = create_d_file(
+        type_defs.clone(),
+        write_path.clone(),
+        &paths,
+        &cache,
+        failures,
+        &walker_hash,
+        &vm,
+    )?;
     let lua_addon_storage = create_lua_addon(
         paths.lua_addon.clone().unwrap_or(LuaAddon::False),
         type_defs.clone(),
         definition_file_storage.clone(),
         paths.is_global,
         &paths.name,
+        &cache,
+        &walker_hash,
     )
     .context("Failed generating lua language server addon")?;
 
@@ -50,17 +111,42 @@ pub(crate) fn run_from_walker(
         );
     }
 
-    let link_path = Path::new("/").join(&paths.root);
+    //every page is written to the same folder, so a relative link only needs the file name
+    let link_path = if paths.relative_links {
+        PathBuf::new()
+    } else {
+        Path::new("/").join(&paths.root)
+    };
 
     let mut z = RecordGenerator::new::<RecordGenerator>(true);
 
     let sidebar = generate_sidebar_data(&type_defs, &paths, &link_path);
+    let search_index = generate_search_index(&type_defs, &paths, &link_path)
+        .context("Failed generating the search index")?;
+    //browsers don't allow fetching the json when the pages are opened from disk, so the builtin template loads the script instead
+    let search_index_script = search_index_script(&search_index);
+    for (file_name, contents) in [
+        (SEARCH_INDEX_JSON, search_index),
+        (SEARCH_INDEX_SCRIPT, search_index_script),
+    ] {
+        let path = write_path.join(file_name);
+        let hash = cache::hash(&[contents.as_bytes()]);
+        if !cache.is_fresh(&path, &hash) {
+            std::fs::write(&path, contents)
+                .with_context(|| format!("Could not write {file_name}"))?;
+            cache.built(&path, hash);
+        }
+    }
+    let search_index_url = link_path
+        .join(SEARCH_INDEX_SCRIPT)
+        .to_string_lossy()
+        .into_owned();
+    let mut pages = Vec::new();
     for type_def in type_defs.iter() {
-        let users = crate::find_uses::find_users(type_def, &type_defs);
         match type_def {
             TypeGenerator::Record(x) => {
-                let x = x.to_owned();
                 if x.should_be_inlined {
+                    let x = x.to_owned();
                     z.documentation.extend(x.documentation);
                     z.fields.extend(x.fields);
                     z.functions.extend(x.functions);
@@ -78,81 +164,187 @@ pub(crate) fn run_from_walker(
             }
             TypeGenerator::Enum(_) => (),
         }
-        let definition_templates = paths.def_config.templates.clone();
-        let (template_runner, docs_instance) =
-            create_globals_docs(&paths.template_kind, type_def, |config| {
-                GlobalInstancesDoc {
-                    side_bar: sidebar.clone(),
-                    link_path: link_path.clone(),
-                    etlua: config.etlua,
-                    template: config.template,
-                    page: TypeOrPage::Type(TypeDesc {
-                        type_members: type_def.to_owned(),
-                        type_name: config.type_name.to_string(),
-                        used_by: users,
-                    }),
-                    all_types: Some(type_defs.given_types.clone()),
-                    globals: Some(type_defs.global_instances_off.clone()),
-                    def_files: definition_templates.clone(),
-                    library_name: paths.name.clone(),
-                    definition_files_folder: definition_file_storage.to_string_lossy().to_string(),
-                }
-            })?;
-        let name = match &docs_instance.page {
-            TypeOrPage::Type(x) => x.type_name.clone(),
-            _ => unreachable!(),
-        };
-        run_and_write(&write_path, &template_runner, docs_instance)
-            .with_context(|| format!("Failed while generating file for: {name}"))?;
+        pages.push(TypeOrPage::Type(TypeDesc {
+            type_members: type_def.to_owned(),
+            type_name: page_name(type_def).to_string(),
+            used_by: crate::find_uses::find_users(type_def, &type_defs),
+        }));
     }
     let type_def = TypeGenerator::Record(Box::new(z));
-    let (template_runner, mut docs_instance) =
-        create_globals_docs(&paths.template_kind, &type_def, |config| {
-            GlobalInstancesDoc {
-                side_bar: sidebar.clone(),
-                link_path,
-                etlua: config.etlua,
-                template: config.template,
-                page: TypeOrPage::IndexPage(IndexPage {
-                    type_members: type_def.to_owned(),
-                    type_name: config.type_name.to_string(),
-                    all_types: type_defs.given_types.clone(),
-                }),
-                all_types: Some(type_defs.given_types.clone()),
-                globals: Some(type_defs.global_instances_off.clone()),
-                def_files: paths.def_config.templates.clone(),
-                library_name: paths.name.clone(),
-                definition_files_folder: definition_file_storage.to_string_lossy().to_string(),
-            }
-        })?;
-    run_and_write(&write_path, &template_runner, docs_instance.clone())
-        .context("Error while generating file for the index page")?;
+    pages.push(TypeOrPage::IndexPage(IndexPage {
+        type_name: page_name(&type_def).to_string(),
+        type_members: type_def,
+        all_types: type_defs.given_types.clone(),
+    }));
     for custom in &type_defs.extra_page {
-        docs_instance.page = TypeOrPage::CustomPage(CustomPage {
+        pages.push(TypeOrPage::CustomPage(CustomPage {
             name: custom.name.clone(),
             markdown_content: custom.content.clone(),
-        });
-        run_and_write(&write_path, &template_runner, docs_instance.clone()).with_context(|| {
-            format!("Error while generating custom page named: {}", custom.name)
-        })?;
+        }));
     }
-    Ok(())
+
+    let templates = load_templates(&paths.template_kind, vm)?;
+    //the pages show where the definition files end up, while the examples in them get compiled against the ones in the staging folder
+    let definition_files_build_folder = definition_file_storage.to_string_lossy().to_string();
+    let definition_files_folder = target
+        .join(
+            definition_file_storage
+                .strip_prefix(&write_path)
+                .unwrap_or(&definition_file_storage),
+        )
+        .to_string_lossy()
+        .to_string();
+    //every page gets access to these, so a change in any of them means every page has to be generated again
+    let shared_hash = cache::hash(&[
+        templates.runner.as_bytes(),
+        templates.template.as_bytes(),
+        cache::stable_json(&sidebar)?.as_bytes(),
+        search_index_url.as_bytes(),
+        link_path.to_string_lossy().as_bytes(),
+        paths.name.as_bytes(),
+        definition_files_folder.as_bytes(),
+        definition_files_build_folder.as_bytes(),
+        cache::stable_json(&paths.def_config.templates)?.as_bytes(),
+        cache::stable_json(&type_defs.global_instances_off)?.as_bytes(),
+        //templates can read every type through `all_types`, `type_to_link` and `create_link`
+        cache::stable_json(&type_defs.given_types)?.as_bytes(),
+    ]);
+    let globals = GlobalInstancesDoc {
+        side_bar: sidebar,
+        search_index: search_index_url,
+        link_path,
+        etlua: templates.etlua.clone(),
+        template: templates.template.clone(),
+        all_types: Some(Arc::new(type_defs.given_types)),
+        globals: Some(type_defs.global_instances_off),
+        def_files: paths.def_config.templates,
+        library_name: paths.name,
+        definition_files_folder,
+        definition_files_build_folder,
+        ..Default::default()
+    };
+    render_pages(
+        &write_path,
+        &templates,
+        globals,
+        pages,
+        &cache,
+        failures,
+        &shared_hash,
+    )?;
+    cache.finish()
+}
+
+/// Shows the diagnostics of a run that only generates some of the files, failing it if any of them are denied
+fn finish_partial_run<T>(paths: &Paths, res: Result<T, anyhow::Error>) -> Result<T, anyhow::Error> {
+    let diagnostics_res =
+        diagnostics::finish(paths.strict, &paths.diagnostics, paths.message_format);
+    let value = res?;
+    diagnostics_res?;
+    Ok(value)
+}
+
+/// Generates only the definition files, in a `definitions` folder inside of `write_path`
+pub(crate) fn run_definition_files(
+    paths: &Paths,
+    type_defs: TypeWalker,
+    write_path: &Path,
+) -> Result<PathBuf, anyhow::Error> {
+    let res = generate_definition_files(paths, type_defs, write_path);
+    finish_partial_run(paths, res)
+}
+
+fn generate_definition_files(
+    paths: &Paths,
+    type_defs: TypeWalker,
+    write_path: &Path,
+) -> Result<PathBuf, anyhow::Error> {
+    create_dir_all(write_path)
+        .with_context(|| format!("Could not create {}", write_path.display()))?;
+    let failures = Failures::new(paths.keep_going);
+    let cache = BuildCache::load_named(
+        write_path,
+        cache::partial_manifest_name("definitions", &paths.name),
+    );
+    let walker_hash = cache::hash(&[cache::stable_json(&type_defs)?.as_bytes()]);
+    let vm = VmOptions::new(paths.sandbox.clone(), write_path)?;
+    let folder = create_d_file(
+        type_defs,
+        write_path.to_owned(),
+        paths,
+        &cache,
+        &failures,
+        &walker_hash,
+        &vm,
+    )?;
+    cache.finish()?;
+    failures.finish()?;
+    Ok(folder)
+}
+
+/// Generates only the lua language server addon, inside of `write_path`
+pub(crate) fn run_lua_addon(
+    paths: &Paths,
+    type_defs: TypeWalker,
+    write_path: &Path,
+) -> Result<bool, anyhow::Error> {
+    let res = generate_lua_addon(paths, type_defs, write_path);
+    finish_partial_run(paths, res)
+}
+
+fn generate_lua_addon(
+    paths: &Paths,
+    type_defs: TypeWalker,
+    write_path: &Path,
+) -> Result<bool, anyhow::Error> {
+    create_dir_all(write_path)
+        .with_context(|| format!("Could not create {}", write_path.display()))?;
+    let cache = BuildCache::load_named(
+        write_path,
+        cache::partial_manifest_name("lua_addon", &paths.name),
+    );
+    let walker_hash = cache::hash(&[cache::stable_json(&type_defs)?.as_bytes()]);
+    let created = create_lua_addon(
+        paths.lua_addon.clone().unwrap_or(LuaAddon::False),
+        type_defs,
+        write_path.to_owned(),
+        paths.is_global,
+        &paths.name,
+        &cache,
+        &walker_hash,
+    )
+    .context("Failed generating lua language server addon")?;
+    cache.finish()?;
+    Ok(created)
 }
 
 pub(crate) fn run_template(paths: Paths) -> Result<(), anyhow::Error> {
-    let json = read_to_string(&paths.json)
-        .with_context(|| format!("Failed reading type json at location: {}", paths.json))?;
+    let json = if paths.json == "-" {
+        let mut json = String::new();
+        std::io::stdin()
+            .read_to_string(&mut json)
+            .map(|_| json)
+            .context("Failed reading type json from stdin")?
+    } else {
+        read_to_string(&paths.json)
+            .with_context(|| format!("Failed reading type json at location: {}", paths.json))?
+    };
     let value = serde_json::from_str::<serde_json::Value>(&json)
         .with_context(|| format!("Failed deserializing given type file at: {}", paths.json))?;
     let type_defs: tealr::TypeWalker = match serde_json::from_value::<TypeWalker>(value.clone()) {
         Ok(x) => {
             if !x.check_correct_version() {
-                eprintln!("Warning:");
-                eprintln!("Tealr version used to create this json is not equal to the tealr version used to build this version of tealr_doc_gen");
-                eprintln!("Tealr version used: {}", x.get_tealr_version_used());
-                eprintln!("built version used: {}", tealr::get_tealr_version());
-                eprintln!("Please update both tealr and tealr_doc_gen so the versions match.");
-                eprintln!("Schema seems compatible. Trying anyway");
+                diagnostics::report(
+                    DiagnosticCode::VersionMismatch,
+                    Some(Location::Input {
+                        path: paths.json.clone(),
+                    }),
+                    format!(
+                        "Tealr version used to create this json is not equal to the tealr version used to build this version of tealr_doc_gen.\nTealr version used: {}\nbuilt version used: {}\nPlease update both tealr and tealr_doc_gen so the versions match.\nSchema seems compatible. Trying anyway",
+                        x.get_tealr_version_used(),
+                        tealr::get_tealr_version()
+                    ),
+                );
             }
             x
         }
@@ -0,0 +1,376 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use tealr::mlu::mlua::{self, HookTriggers, Lua, VmState};
+
+use crate::app::SandboxConfig;
+
+/// How many instructions run between checks of the instruction limit
+const INSTRUCTIONS_PER_CHECK: u32 = 1000;
+
+/// Replaces everything that can reach outside of the project with restricted versions.
+///
+/// Gets called with the function that checks if a path may be opened with the given mode, like `"r"` or `"w"`.
+/// The replacements only use locals, as templates can change globals like `string.find`.
+/// Without globals they also have no `_ENV` that `setfenv` could swap out.
+const RESTRICT_GLOBALS: &str = r##"
+local check_path = ...
+local io_open, io_lines, io_input, io_output = io.open, io.lines, io.input, io.output
+local lua_dofile, lua_loadfile, lua_load = dofile, loadfile, load
+local getupvalue, upvaluejoin = debug.getupvalue, debug.upvaluejoin
+local type, select, error, assert = type, select, error, assert
+local package, searchpath = package, package.searchpath
+
+--the teal compiler that is installed on the system stays available for `teal_lua` snippets,
+--even though it is outside of the folders that templates can read from
+local tl_path = searchpath("tl", package.path)
+if tl_path then
+    package.preload.tl = function(...)
+        return assert(lua_loadfile(tl_path, "t"))(...)
+    end
+end
+
+--the functions below can't have their environment replaced
+local protected = setmetatable({}, { __mode = "k" })
+
+--etlua needs setfenv, which on lua 5.4 can only be made with the debug library.
+--So only that part of it stays available
+local function restricted_setfenv(fn, env)
+    if protected[fn] then
+        error("Sandboxed templates can not change the environment of the sandbox", 2)
+    end
+    local name
+    local i = 1
+    while true do
+        name = getupvalue(fn, i)
+        if not name or name == "_ENV" then break end
+        i = i + 1
+    end
+    if name then upvaluejoin(fn, i, function() return env end, 1) end
+    return fn
+end
+setfenv = restricted_setfenv
+debug = nil
+package.loaded.debug = nil
+
+io.open = function(path, mode)
+    mode = mode or "r"
+    return io_open(check_path(path, mode), mode)
+end
+io.lines = function(path, ...)
+    if path == nil then
+        return io_lines()
+    end
+    return io_lines(check_path(path, "r"), ...)
+end
+io.input = function(file)
+    if type(file) == "string" then
+        file = check_path(file, "r")
+    end
+    return io_input(file)
+end
+io.output = function(file)
+    if type(file) == "string" then
+        file = check_path(file, "w")
+    end
+    return io_output(file)
+end
+dofile = function(path)
+    return lua_dofile(check_path(path, "r"))
+end
+--an env that is passed as nil is not the same as one that is left out, so it is only passed when it was given
+loadfile = function(path, _, ...)
+    if select("#", ...) > 0 then
+        return lua_loadfile(check_path(path, "r"), "t", ...)
+    end
+    return lua_loadfile(check_path(path, "r"), "t")
+end
+--precompiled chunks can crash the vm, so only source code can be loaded
+load = function(chunk, name, _, ...)
+    if select("#", ...) > 0 then
+        return lua_load(chunk, name, "t", ...)
+    end
+    return lua_load(chunk, name, "t")
+end
+
+--the default searcher opens files itself, so it gets replaced by one that checks the path first.
+--The searchers for C libraries are removed
+local function search_lua(name)
+    local path, err = searchpath(name, package.path)
+    if not path then
+        return err
+    end
+    return assert(lua_loadfile(check_path(path, "r"), "t")), path
+end
+package.searchers = { package.searchers[1], search_lua }
+
+protected[restricted_setfenv] = true
+protected[search_lua] = true
+if tl_path then
+    protected[package.preload.tl] = true
+end
+for _, fn in ipairs({ io.open, io.lines, io.input, io.output, dofile, loadfile, load }) do
+    protected[fn] = true
+end
+
+io.popen = nil
+os.execute = nil
+os.exit = nil
+--environment variables can contain secrets, like the tokens of a CI run
+os.getenv = nil
+os.remove = nil
+os.rename = nil
+os.tmpname = nil
+package.loadlib = nil
+package.cpath = ""
+package.path = "./?.lua;./?/init.lua"
+"##;
+
+struct InstructionBudget {
+    used: u64,
+    limit: u64,
+}
+
+#[derive(Clone, Default)]
+/// The folders a sandboxed template is allowed to access
+struct AllowedFolders {
+    /// Folders that can be read from
+    read: Vec<PathBuf>,
+    /// Folders that can be read from and written to
+    write: Vec<PathBuf>,
+}
+
+/// Decides how the lua vms that run templates and runners get created
+pub(super) struct VmOptions {
+    sandbox: Option<SandboxConfig>,
+    allowed_folders: AllowedFolders,
+}
+
+/// Resolves `path` and makes sure it is inside one of the allowed folders.
+///
+/// Templates can read files inside of the project, but only write to the output folder.
+/// Otherwise a template could for example write a git hook or `build.rs`, which runs outside of the sandbox.
+fn check_path(allowed_folders: &AllowedFolders, path: &str, write: bool) -> mlua::Result<String> {
+    let absolute = std::env::current_dir()
+        .map_err(mlua::Error::external)?
+        .join(path);
+    //files that don't exist yet can't be canonicalized, so their folder is used instead
+    let resolved = match absolute.canonicalize() {
+        Ok(x) => Some(x),
+        Err(_) => absolute
+            .parent()
+            .and_then(|x| x.canonicalize().ok())
+            .zip(absolute.file_name())
+            .map(|(folder, name)| folder.join(name)),
+    };
+    let folders = if write {
+        allowed_folders.write.iter().collect::<Vec<_>>()
+    } else {
+        allowed_folders
+            .read
+            .iter()
+            .chain(&allowed_folders.write)
+            .collect()
+    };
+    match resolved {
+        Some(resolved) if folders.iter().any(|x| resolved.starts_with(x)) => {
+            Ok(resolved.to_string_lossy().into_owned())
+        }
+        _ if write => Err(mlua::Error::runtime(format!(
+            "Sandboxed templates can not write to {path}, only to files inside the output folder"
+        ))),
+        _ => Err(mlua::Error::runtime(format!(
+            "Sandboxed templates can not access {path}, only files inside the project and output folders"
+        ))),
+    }
+}
+
+/// Returns true if a file opened with `mode`, as passed to `io.open`, can be written to
+fn mode_writes(mode: &str) -> bool {
+    mode.contains(['w', 'a', '+'])
+}
+
+fn resolve_folder(folder: PathBuf) -> Result<PathBuf, anyhow::Error> {
+    folder
+        .canonicalize()
+        .with_context(|| format!("Could not resolve {}", folder.display()))
+}
+
+impl VmOptions {
+    /// Sandboxed vms can read from the current directory and `write_path`, and only write to `write_path`
+    pub(super) fn new(
+        sandbox: Option<SandboxConfig>,
+        write_path: &Path,
+    ) -> Result<Self, anyhow::Error> {
+        let allowed_folders = match sandbox {
+            None => AllowedFolders::default(),
+            Some(_) => AllowedFolders {
+                read: vec![resolve_folder(std::env::current_dir()?)?],
+                write: vec![resolve_folder(write_path.to_owned())?],
+            },
+        };
+        Ok(Self {
+            sandbox,
+            allowed_folders,
+        })
+    }
+
+    /// Creates a vm, restricted if the sandbox is turned on
+    pub(super) fn create_vm(&self) -> Result<Lua, anyhow::Error> {
+        let lua = unsafe { Lua::unsafe_new() };
+        let Some(sandbox) = &self.sandbox else {
+            return Ok(lua);
+        };
+        let allowed_folders = self.allowed_folders.clone();
+        let check = lua.create_function(move |_, (path, mode): (String, String)| {
+            check_path(&allowed_folders, &path, mode_writes(&mode))
+        })?;
+        lua.load(RESTRICT_GLOBALS)
+            .set_name("sandbox")
+            .call::<()>(check)
+            .context("Failed while setting up the sandbox")?;
+        if let Some(limit) = sandbox.memory_limit {
+            lua.set_memory_limit(limit)
+                .context("Failed while setting the memory limit")?;
+        }
+        if let Some(limit) = sandbox.instruction_limit {
+            lua.set_app_data(InstructionBudget { used: 0, limit });
+            lua.set_hook(
+                HookTriggers::new().every_nth_instruction(INSTRUCTIONS_PER_CHECK),
+                |lua, _| {
+                    if let Some(mut budget) = lua.app_data_mut::<InstructionBudget>() {
+                        budget.used += u64::from(INSTRUCTIONS_PER_CHECK);
+                        if budget.used > budget.limit {
+                            return Err(mlua::Error::runtime(format!(
+                                "Template used more than the limit of {} instructions",
+                                budget.limit
+                            )));
+                        }
+                    }
+                    Ok(VmState::Continue)
+                },
+            );
+        }
+        Ok(lua)
+    }
+}
+
+/// Gives the vm its full instruction limit again, so every page gets the same budget
+pub(super) fn reset_instruction_budget(lua: &Lua) {
+    if let Some(mut budget) = lua.app_data_mut::<InstructionBudget>() {
+        budget.used = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_folder;
+
+    #[test]
+    fn writes_are_limited_to_the_output_folder() {
+        let folder = test_folder("writes_are_limited_to_the_output_folder");
+        let project = folder.join("project");
+        let output = project.join("pages");
+        std::fs::create_dir_all(&output).unwrap();
+        std::fs::write(project.join("build.rs"), "").unwrap();
+        let allowed = AllowedFolders {
+            read: vec![project.clone()],
+            write: vec![output.clone()],
+        };
+        let path = |x: &Path| x.to_string_lossy().into_owned();
+
+        assert!(check_path(&allowed, &path(&project.join("build.rs")), false).is_ok());
+        assert!(check_path(&allowed, &path(&project.join("build.rs")), true).is_err());
+        assert!(check_path(
+            &allowed,
+            &path(&project.join(".git/hooks/pre-commit")),
+            true
+        )
+        .is_err());
+        assert!(check_path(&allowed, &path(&output.join("new.html")), true).is_ok());
+        assert!(check_path(&allowed, &path(&output.join("../build.rs")), true).is_err());
+        assert!(check_path(&allowed, &path(&folder.join("outside.txt")), false).is_err());
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn sandboxed_vm_keeps_load_working_and_hides_the_environment() {
+        let folder = test_folder("sandboxed_vm_keeps_load_working_and_hides_the_environment");
+        let options = VmOptions::new(Some(SandboxConfig::default()), &folder).unwrap();
+        let lua = options.create_vm().unwrap();
+        let result: (i64, i64, bool, bool) = lua
+            .load(
+                r#"
+                local with_env = load("return x", "chunk", "t", { x = 2 })
+                return load("return 1")(), with_env(), os.getenv == nil, debug == nil
+                "#,
+            )
+            .eval()
+            .unwrap();
+        assert_eq!(result, (1, 2, true, true));
+
+        let written = folder.join("page.html").to_string_lossy().into_owned();
+        lua.load(format!("assert(io.open({written:?}, \"w\")):close()"))
+            .exec()
+            .unwrap();
+        assert!(lua
+            .load(r#"return io.open("sandbox_write_test.txt", "w")"#)
+            .exec()
+            .is_err());
+        assert!(!Path::new("sandbox_write_test.txt").exists());
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn templates_can_not_change_how_the_sandbox_checks_writes() {
+        let folder = test_folder("templates_can_not_change_how_the_sandbox_checks_writes");
+        let options = VmOptions::new(Some(SandboxConfig::default()), &folder).unwrap();
+        let lua = options.create_vm().unwrap();
+        //the current directory is only readable, so these would be writes outside of the output folder
+        for attack in [
+            r#"string.find = function() end
+            return io.open("sandbox_bypass_test.txt", "w")"#,
+            r#"local fake_string = { find = function() end }
+            setfenv(io.open, { string = fake_string, check_path = function(x) return x end })
+            return io.open("sandbox_bypass_test.txt", "w")"#,
+            r#"setfenv(setfenv, {})"#,
+            r#"return io.open("sandbox_bypass_test.txt", "a+")"#,
+        ] {
+            assert!(lua.load(attack).exec().is_err(), "{attack}");
+        }
+        assert!(!Path::new("sandbox_bypass_test.txt").exists());
+        assert!(mode_writes("r+b"));
+        assert!(!mode_writes("rb"));
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn require_only_finds_modules_inside_the_project() {
+        let folder = test_folder("require_only_finds_modules_inside_the_project");
+        let output = folder.join("pages");
+        std::fs::create_dir_all(&output).unwrap();
+        std::fs::write(folder.join("outside.lua"), "return 1").unwrap();
+        std::fs::write(output.join("inside.lua"), "return 2").unwrap();
+        let options = VmOptions::new(Some(SandboxConfig::default()), &output).unwrap();
+        let lua = options.create_vm().unwrap();
+        let path = |x: &Path| x.to_string_lossy().replace('\\', "/");
+
+        let inside = format!(
+            "package.path = {:?} return require('inside')",
+            path(&output.join("?.lua"))
+        );
+        assert_eq!(lua.load(inside).eval::<i64>().unwrap(), 2);
+        let outside = format!(
+            "package.path = {:?} return require('outside')",
+            path(&folder.join("?.lua"))
+        );
+        assert!(lua.load(outside).exec().is_err());
+        let escaping = format!(
+            "package.path = {:?} return require('outside')",
+            path(&output.join("../?.lua"))
+        );
+        assert!(lua.load(escaping).exec().is_err());
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+}
@@ -0,0 +1,230 @@
+use std::path::Path;
+
+use serde::Serialize;
+use tealr::{
+    ExportedFunction, FunctionRepresentation, RecordGenerator, Type, TypeGenerator, TypeWalker,
+};
+
+use crate::{doc_gen::type_signature, markdown::first_sentence, Paths};
+
+use super::sidebar::type_page_link;
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum EntryKind {
+    Type,
+    Field,
+    StaticField,
+    Method,
+    Function,
+    MetaMethod,
+    MetaFunction,
+    MacroExpression,
+    Variant,
+    Global,
+    Page,
+}
+
+#[derive(Serialize)]
+/// A single searchable item in `search-index.json`
+struct SearchEntry {
+    kind: EntryKind,
+    name: String,
+    /// Name of the type this entry is a member of
+    parent: Option<String>,
+    signature: Option<String>,
+    /// First sentence of the documentation
+    summary: String,
+    url: String,
+}
+
+fn function_signature(function: &ExportedFunction) -> String {
+    type_signature(&Type::Function(FunctionRepresentation {
+        params: function.params.clone(),
+        returns: function.returns.clone(),
+    }))
+}
+
+fn record_entries(
+    record: &RecordGenerator,
+    parent: &str,
+    url: &str,
+    entries: &mut Vec<SearchEntry>,
+) {
+    let summary_of = |name: &tealr::NameContainer| {
+        record
+            .documentation
+            .get(name)
+            .map(|x| first_sentence(x))
+            .unwrap_or_default()
+    };
+    let fields = record.fields.iter().map(|x| (EntryKind::Field, x)).chain(
+        record
+            .static_fields
+            .iter()
+            .map(|x| (EntryKind::StaticField, x)),
+    );
+    for (kind, field) in fields {
+        let name = String::from_utf8_lossy(&field.name).to_string();
+        entries.push(SearchEntry {
+            kind,
+            url: format!("{url}#{name}"),
+            name,
+            parent: Some(parent.to_owned()),
+            signature: Some(type_signature(&field.ty)),
+            summary: summary_of(&field.name),
+        })
+    }
+    let functions = record
+        .methods
+        .iter()
+        .chain(record.mut_methods.iter())
+        .map(|x| (EntryKind::Method, x))
+        .chain(
+            record
+                .functions
+                .iter()
+                .chain(record.mut_functions.iter())
+                .map(|x| (EntryKind::Function, x)),
+        )
+        .chain(
+            record
+                .meta_method
+                .iter()
+                .chain(record.meta_method_mut.iter())
+                .map(|x| (EntryKind::MetaMethod, x)),
+        )
+        .chain(
+            record
+                .meta_function
+                .iter()
+                .chain(record.meta_function_mut.iter())
+                .map(|x| (EntryKind::MetaFunction, x)),
+        );
+    for (kind, function) in functions {
+        let name = String::from_utf8_lossy(&function.name).to_string();
+        entries.push(SearchEntry {
+            kind,
+            url: format!("{url}#{name}"),
+            name,
+            parent: Some(parent.to_owned()),
+            signature: Some(function_signature(function)),
+            summary: summary_of(&function.name),
+        })
+    }
+    for macro_expr in &record.macro_expressions {
+        let name = String::from_utf8_lossy(&macro_expr.name).to_string();
+        entries.push(SearchEntry {
+            kind: EntryKind::MacroExpression,
+            url: format!("{url}#{name}"),
+            name,
+            parent: Some(parent.to_owned()),
+            signature: Some(type_signature(&Type::Function(
+                macro_expr.signature.clone(),
+            ))),
+            summary: summary_of(&macro_expr.name),
+        })
+    }
+}
+
+/// File the search index is stored in as json, for other tools to use
+pub(super) const SEARCH_INDEX_JSON: &str = "search-index.json";
+/// File the search index is stored in as a script assigning [SEARCH_INDEX_GLOBAL]
+pub(super) const SEARCH_INDEX_SCRIPT: &str = "search-index.js";
+/// The global `search-index.js` stores the search index in
+const SEARCH_INDEX_GLOBAL: &str = "tealrDocGenSearchIndex";
+
+/// Wraps the search index in a script, which the builtin template loads with a `<script>` tag.
+///
+/// Unlike `fetch`, that also works when the pages are opened straight from disk.
+pub(super) fn search_index_script(search_index: &str) -> String {
+    format!("window.{SEARCH_INDEX_GLOBAL} = {search_index};\n")
+}
+
+/// Creates the contents of `search-index.json`, which the builtin template uses to search through the documentation without needing a server.
+pub(super) fn generate_search_index(
+    type_defs: &TypeWalker,
+    paths: &Paths,
+    link_path: &Path,
+) -> Result<String, serde_json::Error> {
+    let mut entries = Vec::new();
+    for type_def in type_defs.iter() {
+        let (name, url) = type_page_link(type_def, type_defs, paths, link_path);
+        let url = url.to_string_lossy().into_owned();
+        match type_def {
+            TypeGenerator::Record(record) => {
+                if !record.should_be_inlined {
+                    entries.push(SearchEntry {
+                        kind: EntryKind::Type,
+                        name: name.clone(),
+                        parent: None,
+                        signature: Some(type_signature(&record.ty)),
+                        summary: first_sentence(&record.type_doc),
+                        url: url.clone(),
+                    });
+                }
+                record_entries(record, &name, &url, &mut entries);
+            }
+            TypeGenerator::Enum(enum_generator) => {
+                entries.push(SearchEntry {
+                    kind: EntryKind::Type,
+                    name: name.clone(),
+                    parent: None,
+                    signature: Some(type_signature(&enum_generator.ty)),
+                    summary: first_sentence(&enum_generator.type_doc),
+                    url: url.clone(),
+                });
+                for variant in &enum_generator.variants {
+                    let variant = String::from_utf8_lossy(variant).to_string();
+                    entries.push(SearchEntry {
+                        kind: EntryKind::Variant,
+                        url: format!("{url}#{variant}"),
+                        signature: Some(format!("\"{variant}\"")),
+                        name: variant,
+                        parent: Some(name.clone()),
+                        summary: String::new(),
+                    })
+                }
+            }
+        }
+    }
+    let index = link_path.join("index.html").to_string_lossy().into_owned();
+    for global in &type_defs.global_instances_off {
+        entries.push(SearchEntry {
+            kind: EntryKind::Global,
+            name: global.name.to_string(),
+            parent: None,
+            signature: Some(type_signature(&global.ty)),
+            summary: first_sentence(&global.doc),
+            url: format!("{index}#{}", global.name),
+        })
+    }
+    for page in &type_defs.extra_page {
+        entries.push(SearchEntry {
+            kind: EntryKind::Page,
+            name: page.name.clone(),
+            parent: None,
+            signature: None,
+            summary: first_sentence(&page.content),
+            url: link_path
+                .join(sanitize_filename::sanitize(&page.name))
+                .with_extension("html")
+                .to_string_lossy()
+                .into_owned(),
+        })
+    }
+    serde_json::to_string(&entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script_assigns_the_index_to_a_global() {
+        assert_eq!(
+            search_index_script(r#"[{"name":"a"}]"#),
+            "window.tealrDocGenSearchIndex = [{\"name\":\"a\"}];\n"
+        );
+    }
+}
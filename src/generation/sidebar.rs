@@ -1,14 +1,15 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use tealr::{mlu::FromToLua, NameContainer, ToTypename, TypeWalker};
 
 use crate::{
+    diagnostics::Location,
     doc_gen::{get_type_name, type_should_be_inlined},
     render_type::type_to_link_url,
     Paths,
 };
 
-#[derive(FromToLua, ToTypename, Clone)]
+#[derive(FromToLua, ToTypename, Clone, serde::Serialize)]
 /// An element in the sidebar
 pub(super) struct SideBar {
     /// What url to link to
@@ -18,7 +19,7 @@ pub(super) struct SideBar {
     /// The members of the type
     pub(super) members: Vec<Members>,
 }
-#[derive(FromToLua, ToTypename, Clone)]
+#[derive(FromToLua, ToTypename, Clone, serde::Serialize)]
 /// A member as shown in the sidebar
 pub(super) struct Members {
     /// The name of the member
@@ -56,25 +57,7 @@ pub fn generate_sidebar_data(
                     .map(|v| Members { name: v.clone() })
                     .collect(),
             };
-            let (name, link_to) = if type_should_be_inlined(v) {
-                (paths.name.to_string(), link_path.join("index.html"))
-            } else {
-                let x = get_type_name(v).to_string();
-                let url = match v.type_name() {
-                    tealr::Type::Single(single_type) => type_to_link_url(
-                        single_type.to_owned(),
-                        &type_defs.given_types,
-                        link_path.to_path_buf(),
-                    ),
-                    _ => None,
-                }
-                .unwrap_or_else(|| {
-                    link_path
-                        .join(sanitize_filename::sanitize(&x))
-                        .with_extension("html")
-                });
-                (x, url)
-            };
+            let (name, link_to) = type_page_link(v, type_defs, paths, link_path);
             SideBar {
                 link_to: link_path.join(link_to).to_string_lossy().into_owned(),
                 name,
@@ -83,3 +66,35 @@ pub fn generate_sidebar_data(
         })
         .collect()
 }
+
+/// Returns the name shown for a type together with the url of the page it is documented on
+pub(super) fn type_page_link(
+    v: &tealr::TypeGenerator,
+    type_defs: &TypeWalker,
+    paths: &Paths,
+    link_path: &Path,
+) -> (String, PathBuf) {
+    if type_should_be_inlined(v) {
+        (paths.name.to_string(), link_path.join("index.html"))
+    } else {
+        let x = get_type_name(v).to_string();
+        let url = match v.type_name() {
+            tealr::Type::Single(single_type) => type_to_link_url(
+                single_type.to_owned(),
+                &type_defs.given_types,
+                link_path.to_path_buf(),
+                Some(&Location::Type {
+                    name: x.clone(),
+                    member: None,
+                }),
+            ),
+            _ => None,
+        }
+        .unwrap_or_else(|| {
+            link_path
+                .join(sanitize_filename::sanitize(&x))
+                .with_extension("html")
+        });
+        (x, url)
+    }
+}
@@ -0,0 +1,290 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+/// The folder the documentation gets generated in before it replaces the output folder.
+///
+/// It only replaces the real output folder once everything got generated, so a failed run leaves the previous documentation alone.
+pub(super) struct Staging {
+    target: PathBuf,
+    staging: PathBuf,
+}
+
+/// Gives paths like `.` a name by canonicalizing them, as [sibling] needs the name of the folder.
+fn named_folder(target: &Path) -> Result<PathBuf, anyhow::Error> {
+    if target.file_name().is_some() {
+        return Ok(target.to_owned());
+    }
+    let target = if target.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        target
+    };
+    target
+        .canonicalize()
+        .with_context(|| format!("Could not resolve output folder {}", target.display()))
+}
+
+/// Creates a path next to `target` by adding a suffix to its name.
+///
+/// The suffix can not contain dots, as the definition folder inside of it gets passed to `require`.
+fn sibling(target: &Path, suffix: &str) -> Result<PathBuf, anyhow::Error> {
+    let name = target.file_name().with_context(|| {
+        format!(
+            "Can not generate documentation in {}, it needs to be a named folder",
+            target.display()
+        )
+    })?;
+    let mut name = name.to_owned();
+    name.push(suffix);
+    Ok(target.with_file_name(name))
+}
+
+#[cfg(unix)]
+fn copy_symlink(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(std::fs::read_link(from)?, to)
+}
+
+#[cfg(windows)]
+fn copy_symlink(from: &Path, to: &Path) -> std::io::Result<()> {
+    let link = std::fs::read_link(from)?;
+    if std::fs::metadata(from).is_ok_and(|x| x.is_dir()) {
+        std::os::windows::fs::symlink_dir(link, to)
+    } else {
+        std::os::windows::fs::symlink_file(link, to)
+    }
+}
+
+/// Hard links `from` to `to`, falling back to copying it on file systems without hard links
+pub(super) fn link_or_copy(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::hard_link(from, to).or_else(|_| std::fs::copy(from, to).map(|_| ()))
+}
+
+/// Carries every file that is not `owned` over from `from` to `to`, keeping symlinks as links.
+///
+/// `relative` is the path of `from` inside of the output folder.
+fn carry_over(
+    from: &Path,
+    to: &Path,
+    relative: &Path,
+    owned: &dyn Fn(&Path) -> bool,
+) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let relative = relative.join(entry.file_name());
+        let to = to.join(entry.file_name());
+        //the file type of the entry itself, so symlinks are not followed
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            copy_symlink(&entry.path(), &to)?;
+        } else if file_type.is_dir() {
+            carry_over(&entry.path(), &to, &relative, owned)?;
+        } else if !owned(&relative) {
+            link_or_copy(&entry.path(), &to)?;
+        }
+    }
+    Ok(())
+}
+
+/// Makes sure `target` does not contain the current directory or one of the `inputs`.
+///
+/// The output folder gets replaced as a whole, so everything inside of it that tealr_doc_gen did not generate would be copied over and then deleted.
+fn refuse_project_folders(target: &Path, inputs: &[PathBuf]) -> Result<(), anyhow::Error> {
+    let Ok(resolved) = target.canonicalize() else {
+        //a folder that doesn't exist yet can't contain anything
+        return Ok(());
+    };
+    let cwd = std::env::current_dir().ok();
+    for path in cwd.iter().chain(inputs) {
+        if path.canonicalize().is_ok_and(|x| x.starts_with(&resolved)) {
+            anyhow::bail!(
+                "Can not generate documentation in {}, as it contains {}. Use a folder that only holds the documentation",
+                target.display(),
+                path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+impl Staging {
+    /// Creates an empty staging folder next to `target`.
+    ///
+    /// Fails if `target` contains the current directory or one of the `inputs`, like the config file.
+    pub(super) fn create(target: &Path, inputs: &[PathBuf]) -> Result<Self, anyhow::Error> {
+        let target = named_folder(target)?;
+        refuse_project_folders(&target, inputs)?;
+        let staging = sibling(&target, "_tealr_doc_gen_staging")?;
+        if staging.exists() {
+            //left behind by a run that got killed
+            std::fs::remove_dir_all(&staging).with_context(|| {
+                format!("Could not remove old staging folder {}", staging.display())
+            })?;
+        }
+        std::fs::create_dir_all(&staging)
+            .with_context(|| format!("Could not create staging folder {}", staging.display()))?;
+        Ok(Self { target, staging })
+    }
+
+    /// Carries the files in the output folder that tealr_doc_gen did not generate over to the staging folder.
+    ///
+    /// Generated files are skipped, the [BuildCache](super::cache::BuildCache) links the ones that are still fresh itself.
+    /// Hard links are used where possible, nothing writes to these files so they can be shared with the current documentation.
+    pub(super) fn carry_over(&self, owned: impl Fn(&Path) -> bool) -> Result<(), anyhow::Error> {
+        if !self.target.is_dir() {
+            return Ok(());
+        }
+        carry_over(&self.target, &self.staging, Path::new(""), &owned).with_context(|| {
+            format!(
+                "Could not carry {} over to staging folder {}",
+                self.target.display(),
+                self.staging.display()
+            )
+        })
+    }
+
+    pub(super) fn path(&self) -> &Path {
+        &self.staging
+    }
+
+    /// The output folder the staging folder replaces
+    pub(super) fn target(&self) -> &Path {
+        &self.target
+    }
+
+    /// Replaces the output folder with the staging folder.
+    ///
+    /// The old output folder is moved out of the way first and put back if the staging folder can't be moved into place.
+    pub(super) fn commit(self) -> Result<(), anyhow::Error> {
+        let old = sibling(&self.target, "_tealr_doc_gen_old")?;
+        if old.exists() {
+            std::fs::remove_dir_all(&old)
+                .with_context(|| format!("Could not remove old output folder {}", old.display()))?;
+        }
+        let had_target = self.target.exists();
+        if had_target {
+            std::fs::rename(&self.target, &old).with_context(|| {
+                format!("Could not move {} out of the way", self.target.display())
+            })?;
+        }
+        if let Err(x) = std::fs::rename(&self.staging, &self.target) {
+            if had_target {
+                if let Err(x) = std::fs::rename(&old, &self.target) {
+                    eprintln!(
+                        "Could not restore the previous documentation from {}: {x}",
+                        old.display()
+                    );
+                }
+            }
+            return Err(x).with_context(|| {
+                format!(
+                    "Could not move the generated documentation from {} to {}",
+                    self.staging.display(),
+                    self.target.display()
+                )
+            });
+        }
+        if had_target {
+            std::fs::remove_dir_all(&old)
+                .with_context(|| format!("Could not remove old output folder {}", old.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Throws away everything generated in this run
+    pub(super) fn discard(self) {
+        if let Err(x) = std::fs::remove_dir_all(&self.staging) {
+            eprintln!(
+                "Could not remove staging folder {}: {x}",
+                self.staging.display()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_folder;
+
+    #[test]
+    fn sibling_adds_the_suffix_to_the_name() {
+        assert_eq!(
+            sibling(Path::new("out/docs"), "_staging").unwrap(),
+            Path::new("out/docs_staging")
+        );
+        assert!(sibling(Path::new(".."), "_staging").is_err());
+    }
+
+    #[test]
+    fn folders_without_a_name_get_resolved() {
+        for path in ["", "."] {
+            let named = named_folder(Path::new(path)).unwrap();
+            assert!(named.is_absolute());
+            assert_eq!(named, std::env::current_dir().unwrap());
+        }
+        assert_eq!(named_folder(Path::new("docs")).unwrap(), Path::new("docs"));
+    }
+
+    #[test]
+    fn only_files_that_are_not_owned_are_carried_over() {
+        let folder = test_folder("only_files_that_are_not_owned_are_carried_over");
+        let target = folder.join("docs");
+        std::fs::create_dir_all(target.join("sub")).unwrap();
+        std::fs::write(target.join("CNAME"), "docs.example.com").unwrap();
+        std::fs::write(target.join("sub/extra.txt"), "extra").unwrap();
+        std::fs::write(target.join("index.html"), "old").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("CNAME", target.join("link")).unwrap();
+
+        let staging = Staging::create(&target, &[]).unwrap();
+        assert_eq!(staging.path(), folder.join("docs_tealr_doc_gen_staging"));
+        staging
+            .carry_over(|x| x == Path::new("index.html"))
+            .unwrap();
+        let path = staging.path().to_owned();
+        assert_eq!(
+            std::fs::read_to_string(path.join("CNAME")).unwrap(),
+            "docs.example.com"
+        );
+        assert!(path.join("sub/extra.txt").is_file());
+        assert!(!path.join("index.html").exists());
+        #[cfg(unix)]
+        assert_eq!(
+            std::fs::read_link(path.join("link")).unwrap(),
+            Path::new("CNAME")
+        );
+
+        std::fs::write(path.join("index.html"), "new").unwrap();
+        staging.commit().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(target.join("index.html")).unwrap(),
+            "new"
+        );
+        assert!(target.join("sub/extra.txt").is_file());
+        assert!(!folder.join("docs_tealr_doc_gen_staging").exists());
+        assert!(!folder.join("docs_tealr_doc_gen_old").exists());
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn folders_holding_the_project_are_refused() {
+        let folder = test_folder("folders_holding_the_project_are_refused");
+        let project = folder.join("project");
+        let config = project.join("tealr_doc_gen_config.json");
+        std::fs::create_dir_all(&project).unwrap();
+        std::fs::write(&config, "{}").unwrap();
+
+        assert!(Staging::create(&project, &[config.clone()]).is_err());
+        assert!(Staging::create(&folder, &[config.clone()]).is_err());
+        assert!(!folder.join("project_tealr_doc_gen_staging").exists());
+        //the current directory is always part of the project
+        assert!(Staging::create(Path::new("."), &[]).is_err());
+        assert!(Staging::create(Path::new(""), &[]).is_err());
+
+        let staging = Staging::create(&project.join("pages"), &[config]).unwrap();
+        staging.discard();
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+}
@@ -0,0 +1,178 @@
+use tealr::mlu::mlua::{self, Lua};
+
+/// The name etlua gives to the lua code it compiles templates to
+const ETLUA_CHUNK: &str = "[string \"etlua\"]:";
+
+/// How many lines around the failing line are shown
+const EXCERPT_CONTEXT: usize = 2;
+
+/// Compiles the template to lua the same way etlua does, so lines in it can be traced back to the template
+fn compile_to_lua(lua: &Lua, template: &str) -> Option<String> {
+    lua.load("return etlua().Parser():compile_to_lua(...)")
+        .set_name("compile_to_lua")
+        .call::<Option<String>>(template)
+        .ok()
+        .flatten()
+}
+
+/// Finds the line in the template that a line of the compiled lua code came from.
+///
+/// etlua starts the code of every tag with a `--[[position]]` mark, so this walks back to the closest mark.
+/// Code spanning multiple lines is copied as is, so the distance to the mark is the same in the template.
+fn template_line(code: &[&str], template: &str, line: usize) -> Option<usize> {
+    for (offset, code_line) in code.get(..line)?.iter().rev().enumerate() {
+        let Some(rest) = code_line.strip_prefix("--[[") else {
+            continue;
+        };
+        let position: usize = rest.get(..rest.find("]]")?)?.parse().ok()?;
+        let before = template.as_bytes().get(..position.saturating_sub(1))?;
+        let mark_line = before.iter().filter(|x| **x == b'\n').count() + 1;
+        return Some(mark_line + offset);
+    }
+    None
+}
+
+/// Replaces every `[string "etlua"]:line:` in the message with the template name and the line in the template.
+///
+/// Returns the new message and the first line found.
+fn map_message(
+    message: &str,
+    code: &[&str],
+    template: &str,
+    template_name: &str,
+) -> (String, Option<usize>) {
+    let mut mapped = String::with_capacity(message.len());
+    let mut first_line = None;
+    let mut rest = message;
+    while let Some(start) = rest.find(ETLUA_CHUNK) {
+        mapped.push_str(&rest[..start]);
+        rest = &rest[start + ETLUA_CHUNK.len()..];
+        let digits = rest
+            .find(|x: char| !x.is_ascii_digit())
+            .unwrap_or(rest.len());
+        match rest[..digits]
+            .parse()
+            .ok()
+            .and_then(|line| template_line(code, template, line))
+        {
+            Some(line) => {
+                first_line.get_or_insert(line);
+                mapped.push_str(&format!("{template_name}:{line}"));
+            }
+            None => {
+                mapped.push_str(ETLUA_CHUNK);
+                mapped.push_str(&rest[..digits]);
+            }
+        }
+        rest = &rest[digits..];
+    }
+    mapped.push_str(rest);
+    (mapped, first_line)
+}
+
+/// Shows the lines around `line`, marking the line itself
+fn excerpt(template: &str, template_name: &str, line: usize) -> String {
+    let first = line.saturating_sub(EXCERPT_CONTEXT).max(1);
+    let last = line + EXCERPT_CONTEXT;
+    let width = last.to_string().len();
+    let mut excerpt = format!(" --> {template_name}:{line}\n");
+    for (number, source) in template
+        .lines()
+        .enumerate()
+        .map(|(i, x)| (i + 1, x))
+        .skip(first - 1)
+        .take(last - first + 1)
+    {
+        let marker = if number == line { '>' } else { ' ' };
+        excerpt.push_str(&format!("{marker} {number:>width$} | {source}\n"));
+    }
+    excerpt
+}
+
+/// Turns an error raised while running a template into one that points at the template instead of the lua code etlua compiled it to.
+///
+/// Errors that did not come from the compiled template are returned unchanged.
+pub(super) fn map_template_error(
+    lua: &Lua,
+    error: mlua::Error,
+    template: &str,
+    template_name: &str,
+) -> anyhow::Error {
+    let message = error.to_string();
+    if !message.contains(ETLUA_CHUNK) {
+        return error.into();
+    }
+    let Some(code) = compile_to_lua(lua, template) else {
+        return error.into();
+    };
+    let code = code.lines().collect::<Vec<_>>();
+    let (message, line) = map_message(&message, &code, template, template_name);
+    match line {
+        Some(line) => anyhow::anyhow!("{message}\n\n{}", excerpt(template, template_name, line)),
+        None => anyhow::anyhow!(message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPLATE: &str = "a\nb\n<% foo()\nbar() %>\nc";
+    const CODE: &[&str] = &["local _b = { }", "--[[5]] foo()", "bar()"];
+
+    #[test]
+    fn lines_are_counted_from_the_closest_mark() {
+        assert_eq!(template_line(CODE, TEMPLATE, 2), Some(3));
+        //code spanning multiple lines keeps its distance to the mark
+        assert_eq!(template_line(CODE, TEMPLATE, 3), Some(4));
+        assert_eq!(template_line(CODE, TEMPLATE, 1), None);
+        assert_eq!(template_line(CODE, TEMPLATE, 10), None);
+    }
+
+    #[test]
+    fn every_etlua_location_in_the_message_is_mapped() {
+        let message = "runtime error: [string \"etlua\"]:3: boom\nstack traceback:\n\t[string \"etlua\"]:2: in main chunk\n\t[string \"etlua\"]:1: in ?";
+        let (mapped, line) = map_message(message, CODE, TEMPLATE, "page.etlua");
+        assert_eq!(
+            mapped,
+            "runtime error: page.etlua:4: boom\nstack traceback:\n\tpage.etlua:3: in main chunk\n\t[string \"etlua\"]:1: in ?"
+        );
+        assert_eq!(line, Some(4));
+    }
+
+    #[test]
+    fn excerpt_marks_the_failing_line() {
+        let template = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12";
+        assert_eq!(
+            excerpt(template, "page.etlua", 1),
+            " --> page.etlua:1\n> 1 | 1\n  2 | 2\n  3 | 3\n"
+        );
+        assert_eq!(
+            excerpt(template, "page.etlua", 9),
+            " --> page.etlua:9\n   7 | 7\n   8 | 8\n>  9 | 9\n  10 | 10\n  11 | 11\n"
+        );
+    }
+
+    #[test]
+    fn errors_of_a_running_template_point_at_the_template() {
+        let lua = unsafe { Lua::unsafe_new() };
+        let etlua = lua
+            .load(include_str!("../../etlua.lua"))
+            .set_name("etlua")
+            .into_function()
+            .unwrap();
+        lua.globals().set("etlua", etlua).unwrap();
+        let template = "<h1>title</h1>\n<% local x = 1 %>\n<p><%= missing.field %></p>\n";
+        let error = lua
+            .load("return assert(etlua().compile(...))()")
+            .call::<String>(template)
+            .unwrap_err();
+        let message = map_template_error(&lua, error, template, "page.etlua").to_string();
+        assert!(message.contains("page.etlua:3:"), "{message}");
+        assert!(!message.contains(ETLUA_CHUNK), "{message}");
+        assert!(
+            message.contains("> 3 | <p><%= missing.field %></p>"),
+            "{message}"
+        );
+    }
+}
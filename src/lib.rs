@@ -0,0 +1,116 @@
+//! Generates online documentation, definition files and lua language server addons for apis made with [tealr].
+//!
+//! Besides the `tealr_doc_gen` command line tool, the generation can be started from rust code using [DocGenerator].
+//! This skips having to write the types to a json file and creating a config file, making it usable from a `build.rs` or xtask.
+use app::Paths;
+use generation::{generate_self_def, generate_self_doc, run_from_walker, run_template};
+
+use crate::app::get_paths;
+
+mod app;
+mod config_format;
+mod credits;
+mod diagnostics;
+mod doc_gen;
+mod doc_generator;
+mod find_uses;
+mod generation;
+mod markdown;
+mod render_type;
+mod schema;
+mod serve;
+#[cfg(test)]
+mod test_util;
+mod watch;
+
+pub use app::{
+    DefTemplateConfig, DefTemplateKind, DefTemplateRunnerKind, DiagnosticsConfig, LuaAddon,
+    SandboxConfig, TemplateKind, TypeDefFile,
+};
+pub use diagnostics::{DiagnosticCode, MessageFormat};
+pub use doc_generator::DocGenerator;
+
+/// Runs the command line tool, using the arguments the program got started with.
+#[doc(hidden)]
+pub fn run_cli() -> anyhow::Result<()> {
+    match get_paths()? {
+        app::Modes::Credits => {
+            credits::show_credits();
+        }
+        app::Modes::GenerateDocs(x) => {
+            //generate_docs::generate_docs(x)
+            run_template(*x)?;
+        }
+        app::Modes::Watch(x) => {
+            watch::watch(x, || ())?;
+        }
+        app::Modes::Serve { args, port } => {
+            serve::serve(args, port)?;
+        }
+        app::Modes::SelfDocTemplate { build_dir } => {
+            let walker = generate_self_doc()?;
+            run_from_walker(
+                Paths {
+                    is_global: true,
+                    json: "{}".into(),
+                    config_file: None,
+                    name: "tealr_doc_gen".into(),
+                    root: "".into(),
+                    build_dir,
+                    template_kind: app::TemplateKind::Builtin,
+                    def_config: Default::default(),
+                    lua_addon: Some(app::LuaAddon::Create {
+                        words: Default::default(),
+                        files: Default::default(),
+                        settings: Default::default(),
+                        generic_class_fallback: false,
+                        enum_tables: Default::default(),
+                    }),
+                    strict: false,
+                    diagnostics: Default::default(),
+                    message_format: Default::default(),
+                    relative_links: false,
+                    keep_going: false,
+                    sandbox: None,
+                },
+                walker,
+            )?;
+        }
+
+        app::Modes::GenFile { file, location } => {
+            //std::fs::create_dir_all(location)?;
+            std::fs::write(location, file)?;
+        }
+        app::Modes::Nothing => (),
+        app::Modes::SelfDefTemplate { build_dir } => {
+            let walker = generate_self_def()?;
+            run_from_walker(
+                Paths {
+                    is_global: true,
+                    json: "{}".into(),
+                    config_file: None,
+                    name: "tealr_doc_gen".into(),
+                    root: "".into(),
+                    build_dir,
+                    template_kind: app::TemplateKind::Builtin,
+                    def_config: Default::default(),
+                    lua_addon: Some(app::LuaAddon::Create {
+                        words: Default::default(),
+                        files: Default::default(),
+                        settings: Default::default(),
+                        generic_class_fallback: false,
+                        enum_tables: Default::default(),
+                    }),
+                    strict: false,
+                    diagnostics: Default::default(),
+                    message_format: Default::default(),
+                    relative_links: false,
+                    keep_going: false,
+                    sandbox: None,
+                },
+                walker,
+            )?;
+        }
+    }
+    Ok(())
+}
@@ -583,3 +583,51 @@ pub(crate) fn parse_markdown_lua(
     html::push_html(&mut html_output, transformed);
     Ok(html_output)
 }
+
+/// Returns the first sentence of the first paragraph as plain text, stripped of any markdown.
+pub(crate) fn first_sentence(markdown: &str) -> String {
+    let mut text = String::new();
+    let mut in_code_block = false;
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+            Event::End(TagEnd::CodeBlock) => in_code_block = false,
+            Event::Text(x) | Event::Code(x) if !in_code_block => text.push_str(&x),
+            Event::SoftBreak | Event::HardBreak => text.push(' '),
+            Event::End(TagEnd::Paragraph) | Event::End(TagEnd::Heading(_)) => {
+                if !text.trim().is_empty() {
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match text.find(". ") {
+        Some(end) => text[..=end].to_string(),
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::first_sentence;
+
+    #[test]
+    fn first_sentence_stops_at_the_first_sentence() {
+        assert_eq!(
+            first_sentence("Opens a *connection*. Then waits for it."),
+            "Opens a connection."
+        );
+        assert_eq!(first_sentence("No full stop"), "No full stop");
+        assert_eq!(first_sentence(""), "");
+    }
+
+    #[test]
+    fn first_sentence_skips_code_blocks_and_joins_lines() {
+        assert_eq!(
+            first_sentence("```lua\nlocal x = 1\n```\nUses `x`\nto count.\n\nSecond paragraph."),
+            "Uses x to count."
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use std::{collections::HashSet, path::PathBuf, sync::Mutex};
+use std::path::PathBuf;
 
 use tealr::{
     mlu::{
@@ -9,6 +9,8 @@ use tealr::{
     ToTypename, Type, TypeBody, TypeGenerator,
 };
 
+use crate::diagnostics::{self, DiagnosticCode, Location};
+
 pub struct RenderOptions<X: ToTypename> {
     function: TypedFunction<(FunctionRepresentation, X), String>,
     single: TypedFunction<(tealr::SingleType, X), String>,
@@ -217,19 +219,11 @@ impl ToTypename for SingleTypeNoConsume {
         SingleType::to_function_param()
     }
 }
-#[derive(PartialEq, Eq, Hash)]
-pub enum WarningType {
-    UnknownType(Name),
-    MultipleTypes(Name),
-}
-
-static EMITTED_WARNINGS: std::sync::LazyLock<Mutex<HashSet<WarningType>>> =
-    std::sync::LazyLock::new(|| Mutex::new(HashSet::new()));
-
 pub fn type_to_link_url(
     ty: impl Into<SingleTypeNoConsume>,
     all_types: &[TypeGenerator],
     mut link_path: PathBuf,
+    location: Option<&Location>,
 ) -> Option<PathBuf> {
     let ty = ty.into();
     let mut x = all_types
@@ -242,25 +236,26 @@ pub fn type_to_link_url(
         .take(2);
     let Some((found_type, generator)) = x.next() else {
         if ty.name == Name::from("index") {
+            if link_path.as_os_str().is_empty() {
+                return Some(PathBuf::from("index.html"));
+            }
             return Some(link_path);
         }
-        if EMITTED_WARNINGS
-            .lock()
-            .unwrap()
-            .insert(WarningType::UnknownType(ty.name.clone()))
-        {
-            eprintln!("Tried making link to unknown type: {}", ty.name);
-        }
-
+        diagnostics::report_once(
+            DiagnosticCode::UnknownLink,
+            ty.name.to_string(),
+            location.cloned(),
+            format!("Tried making link to unknown type: {}", ty.name),
+        );
         return None;
     };
-    if x.next().is_some()
-        && EMITTED_WARNINGS
-            .lock()
-            .unwrap()
-            .insert(WarningType::MultipleTypes(found_type.name.clone()))
-    {
-        eprintln!("Multiple types with the same name: {}", found_type.name);
+    if x.next().is_some() {
+        diagnostics::report_once(
+            DiagnosticCode::DuplicateType,
+            found_type.name.to_string(),
+            location.cloned(),
+            format!("Multiple types with the same name: {}", found_type.name),
+        );
     }
     let name = if generator.is_inlined() {
         "index".to_string()
@@ -271,3 +266,74 @@ pub fn type_to_link_url(
     link_path.set_extension("html");
     Some(link_path)
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::diagnostics::Collector;
+
+    fn single(name: &str) -> SingleType {
+        serde_json::from_value(json!({ "name": name, "kind": "External", "generics": [] }))
+            .expect("a valid type")
+    }
+
+    fn record(name: &str, should_be_inlined: bool) -> TypeGenerator {
+        let mut record = serde_json::to_value(RecordGenerator::new::<RecordGenerator>(false))
+            .expect("records can be serialized");
+        record["ty"] = json!({ "Single": { "name": name, "kind": "External", "generics": [] } });
+        record["should_be_inlined"] = json!(should_be_inlined);
+        TypeGenerator::Record(Box::new(
+            serde_json::from_value(record).expect("a valid record"),
+        ))
+    }
+
+    fn link(name: &str, link_path: PathBuf) -> Option<PathBuf> {
+        let all_types = [record("Lib", true), record("Foo", false)];
+        type_to_link_url(single(name), &all_types, link_path, None)
+    }
+
+    #[test]
+    fn links_go_to_the_page_of_the_type() {
+        assert_eq!(link("Foo", PathBuf::new()), Some(PathBuf::from("Foo.html")));
+        assert_eq!(
+            link("Foo", PathBuf::from("/docs")),
+            Some(PathBuf::from("/docs/Foo.html"))
+        );
+    }
+
+    #[test]
+    fn inlined_types_link_to_the_index() {
+        assert_eq!(
+            link("Lib", PathBuf::new()),
+            Some(PathBuf::from("index.html"))
+        );
+        assert_eq!(
+            link("Lib", PathBuf::from("/docs")),
+            Some(PathBuf::from("/docs/index.html"))
+        );
+    }
+
+    #[test]
+    fn the_index_type_links_to_the_index_page() {
+        //an empty link path would otherwise become an empty url, which links to nothing
+        assert_eq!(
+            link("index", PathBuf::new()),
+            Some(PathBuf::from("index.html"))
+        );
+        assert_eq!(
+            link("index", PathBuf::from("/docs")),
+            Some(PathBuf::from("/docs"))
+        );
+    }
+
+    #[test]
+    fn unknown_types_are_reported() {
+        let collector = Collector::default();
+        assert_eq!(collector.scope(|| link("Bar", PathBuf::new())), None);
+        let diagnostics = collector.take();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, DiagnosticCode::UnknownLink);
+    }
+}
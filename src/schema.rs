@@ -0,0 +1,444 @@
+use serde_json::{json, Value};
+
+use crate::app::Config;
+
+/// JSON Schema of the config file
+pub(crate) fn config_schema() -> Result<String, anyhow::Error> {
+    Ok(serde_json::to_string_pretty(&schemars::schema_for!(
+        Config
+    ))?)
+}
+
+fn reference(name: &str) -> Value {
+    json!({ "$ref": format!("#/definitions/{name}") })
+}
+
+fn list_of(name: &str) -> Value {
+    json!({ "type": "array", "items": reference(name) })
+}
+
+/// An object with every property required, as tealr always writes every field
+fn object(description: &str, properties: Value) -> Value {
+    let required = properties
+        .as_object()
+        .map(|x| x.keys().cloned().collect::<Vec<_>>())
+        .unwrap_or_default();
+    json!({
+        "description": description,
+        "type": "object",
+        "required": required,
+        "properties": properties
+    })
+}
+
+/// An enum as serde writes it by default, an object with the name of the variant as its only key
+fn tagged_variants(description: &str, variants: &[(&str, &str, Value)]) -> Value {
+    let variants = variants
+        .iter()
+        .map(|(name, description, content)| {
+            json!({
+                "description": description,
+                "type": "object",
+                "required": [name],
+                "properties": { (*name): content },
+                "additionalProperties": false
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({ "description": description, "oneOf": variants })
+}
+
+/// Every type that can appear inside of the json made by `TypeWalker::to_json`
+fn input_definitions() -> Value {
+    let functions = |description: &str| {
+        json!({
+            "description": description,
+            "type": "array",
+            "items": reference("ExportedFunction")
+        })
+    };
+    let markdown = |description: &str| json!({ "description": description, "type": "string" });
+    let record_properties = [
+        (
+            "should_be_inlined",
+            json!({
+                "description": "Records that get inlined have their members shown on the index page, instead of on a page of their own",
+                "type": "boolean"
+            }),
+        ),
+        ("is_user_data", json!({ "type": "boolean" })),
+        ("ty", reference("Type")),
+        ("fields", list_of("Field")),
+        ("static_fields", list_of("Field")),
+        (
+            "methods",
+            functions("Methods, called like `value:method()`"),
+        ),
+        (
+            "mut_methods",
+            functions("Methods that can change the value"),
+        ),
+        (
+            "functions",
+            functions("Functions, called like `value.function()`"),
+        ),
+        (
+            "mut_functions",
+            functions("Functions that can change the value"),
+        ),
+        (
+            "meta_method",
+            functions("Metamethods like `__add` and `__call`"),
+        ),
+        (
+            "meta_method_mut",
+            functions("Metamethods that can change the value"),
+        ),
+        (
+            "meta_function",
+            functions("Metamethods that don't take the value itself"),
+        ),
+        (
+            "meta_function_mut",
+            functions("Metamethods that don't take the value itself and can change it"),
+        ),
+        (
+            "documentation",
+            json!({
+                "description": "Documentation of the members, by their name. Written in markdown",
+                "type": "object",
+                "additionalProperties": { "type": "string" }
+            }),
+        ),
+        (
+            "type_doc",
+            markdown("Documentation of the record itself. Written in markdown"),
+        ),
+    ];
+    let mut record = object(
+        "A record, like a struct or userdata",
+        Value::Object(
+            record_properties
+                .into_iter()
+                .map(|(name, value)| (name.to_owned(), value))
+                .collect(),
+        ),
+    );
+    //added in later versions of tealr, so older files don't have them
+    record["properties"]["implements"] = json!({
+        "description": "Interfaces the record implements",
+        "type": "array",
+        "items": reference("Type")
+    });
+    record["properties"]["macro_expressions"] = list_of("MacroExpr");
+
+    let definitions = [
+        (
+            "Name",
+            json!({ "description": "Name of a type, without its generics", "type": "string" }),
+        ),
+        (
+            "NameContainer",
+            json!({
+                "description": "Name of a field, function, method or enum variant",
+                "type": "string"
+            }),
+        ),
+        (
+            "KindOfType",
+            json!({
+                "description": "Where a type comes from. External types are the ones that should be part of the TypeWalker",
+                "enum": ["Builtin", "External", "Generic"]
+            }),
+        ),
+        (
+            "SingleType",
+            object(
+                "A named type, like `string` or `Foo<T>`",
+                json!({
+                    "name": reference("Name"),
+                    "kind": reference("KindOfType"),
+                    "generics": list_of("Type")
+                }),
+            ),
+        ),
+        (
+            "FunctionParam",
+            object(
+                "A parameter of a function",
+                json!({
+                    "param_name": {
+                        "description": "Name of the parameter, if it has one",
+                        "oneOf": [reference("Name"), { "type": "null" }]
+                    },
+                    "ty": reference("Type")
+                }),
+            ),
+        ),
+        (
+            "FunctionRepresentation",
+            object(
+                "The signature of a function",
+                json!({ "params": list_of("FunctionParam"), "returns": list_of("Type") }),
+            ),
+        ),
+        (
+            "MapRepresentation",
+            object(
+                "A table with keys and values of a single type each",
+                json!({ "key": reference("Type"), "value": reference("Type") }),
+            ),
+        ),
+        (
+            "Type",
+            tagged_variants(
+                "Any type that teal can express",
+                &[
+                    ("Single", "A named type", reference("SingleType")),
+                    (
+                        "Function",
+                        "A function",
+                        reference("FunctionRepresentation"),
+                    ),
+                    (
+                        "Map",
+                        "A table like `{K : V}`",
+                        reference("MapRepresentation"),
+                    ),
+                    ("Or", "A union of the given types", list_of("Type")),
+                    ("Array", "An array like `{T}`", reference("Type")),
+                    ("Tuple", "A tuple like `{A, B}`", list_of("Type")),
+                    (
+                        "Variadic",
+                        "Any amount of values of the given type, like `T...`",
+                        reference("Type"),
+                    ),
+                ],
+            ),
+        ),
+        (
+            "Field",
+            object(
+                "A field or static field of a record",
+                json!({ "name": reference("NameContainer"), "ty": reference("Type") }),
+            ),
+        ),
+        (
+            "ExportedFunction",
+            object(
+                "A function, method or metamethod of a record",
+                json!({
+                    "name": reference("NameContainer"),
+                    "params": list_of("FunctionParam"),
+                    "returns": list_of("Type"),
+                    "is_meta_method": { "type": "boolean" }
+                }),
+            ),
+        ),
+        (
+            "MacroExpr",
+            object(
+                "A macro expression, which teal expands when compiling",
+                json!({
+                    "name": reference("NameContainer"),
+                    "signature": reference("FunctionRepresentation"),
+                    "is_meta_method": { "type": "boolean" }
+                }),
+            ),
+        ),
+        ("RecordGenerator", record),
+        (
+            "EnumGenerator",
+            object(
+                "An enum, of which every variant is a string",
+                json!({
+                    "ty": reference("Type"),
+                    "variants": list_of("NameContainer"),
+                    "type_doc": markdown("Documentation of the enum. Written in markdown")
+                }),
+            ),
+        ),
+        (
+            "TypeGenerator",
+            tagged_variants(
+                "A type that gets a page of its own",
+                &[
+                    (
+                        "Record",
+                        "A record, like a struct or userdata",
+                        reference("RecordGenerator"),
+                    ),
+                    ("Enum", "An enum", reference("EnumGenerator")),
+                ],
+            ),
+        ),
+        (
+            "GlobalInstance",
+            object(
+                "A value in the global scope, like the ones made with `document_global_instance`",
+                json!({
+                    "name": { "type": "string" },
+                    "ty": reference("Type"),
+                    "doc": markdown("Documentation of the global. Written in markdown")
+                }),
+            ),
+        ),
+        (
+            "ExtraPage",
+            object(
+                "An extra page that gets added to the documentation",
+                json!({
+                    "name": { "type": "string" },
+                    "content": markdown("Contents of the page. Written in markdown")
+                }),
+            ),
+        ),
+    ];
+    Value::Object(
+        definitions
+            .into_iter()
+            .map(|(name, definition)| (name.to_owned(), definition))
+            .collect(),
+    )
+}
+
+/// JSON Schema of the json made by `TypeWalker::to_json`.
+///
+/// The types inside of it belong to tealr, so this is written by hand from what tealr writes.
+/// Properties that are not listed are still allowed, so a newer version of tealr adding a field doesn't make the file invalid.
+pub(crate) fn input_schema() -> Result<String, anyhow::Error> {
+    let schema = json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "TypeWalker",
+        "description": "The types to document, created with `TypeWalker::to_json`",
+        "type": "object",
+        "required": ["tealr_version_used", "given_types", "global_instances_off"],
+        "properties": {
+            "tealr_version_used": {
+                "description": "Version of tealr that created this file. It should be the same as the version tealr_doc_gen was built with",
+                "type": "string",
+                "examples": [tealr::get_tealr_version()]
+            },
+            "given_types": {
+                "description": "Every type given to the TypeWalker",
+                "type": "array",
+                "items": reference("TypeGenerator")
+            },
+            "global_instances_off": {
+                "description": "The global instances, like the ones made with `document_global_instance`",
+                "type": "array",
+                "items": reference("GlobalInstance")
+            },
+            "extra_page": {
+                "description": "Extra pages that get added to the documentation, written in markdown",
+                "type": "array",
+                "items": reference("ExtraPage")
+            }
+        },
+        "definitions": input_definitions()
+    });
+    Ok(serde_json::to_string_pretty(&schema)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonschema::JSONSchema;
+
+    use super::*;
+
+    fn compile(schema: &str) -> JSONSchema {
+        let schema: Value = serde_json::from_str(schema).unwrap();
+        //compiling also checks the schema against the JSON Schema meta schema
+        JSONSchema::compile(&schema)
+            .unwrap_or_else(|x| panic!("invalid schema: {x} at {}", x.instance_path))
+    }
+
+    /// Every way `value` doesn't match the schema, together with where
+    fn errors(schema: &JSONSchema, value: &Value) -> Vec<String> {
+        match schema.validate(value) {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors
+                .map(|x| format!("{x} at {}", x.instance_path))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn every_reference_has_a_definition() {
+        fn check(value: &Value, definitions: &Value) {
+            match value {
+                Value::Object(x) => {
+                    if let Some(reference) = x.get("$ref").and_then(Value::as_str) {
+                        let name = reference.strip_prefix("#/definitions/").unwrap();
+                        assert!(!definitions[name].is_null(), "missing {name}");
+                    }
+                    x.values().for_each(|x| check(x, definitions));
+                }
+                Value::Array(x) => x.iter().for_each(|x| check(x, definitions)),
+                _ => (),
+            }
+        }
+        let schema: Value = serde_json::from_str(&input_schema().unwrap()).unwrap();
+        check(&schema, &schema["definitions"]);
+    }
+
+    #[test]
+    fn types_of_tealr_doc_gen_match_the_input_schema() {
+        let schema = compile(&input_schema().unwrap());
+        //these describe tealr's own types, so they use every part of the schema
+        for walker in [
+            crate::generation::generate_self_doc().unwrap(),
+            crate::generation::generate_self_def().unwrap(),
+        ] {
+            let json: Value = serde_json::from_str(&walker.to_json().unwrap()).unwrap();
+            assert!(!json["given_types"].as_array().unwrap().is_empty());
+            assert_eq!(errors(&schema, &json), Vec::<String>::new());
+        }
+    }
+
+    #[test]
+    fn the_input_schema_catches_wrong_types() {
+        let schema = compile(&input_schema().unwrap());
+        let valid = json!({
+            "tealr_version_used": tealr::get_tealr_version(),
+            "given_types": [{
+                "Enum": {
+                    "ty": { "Single": { "name": "Color", "kind": "External", "generics": [] } },
+                    "variants": ["Red", "Green"],
+                    "type_doc": ""
+                }
+            }],
+            "global_instances_off": [{
+                "name": "color",
+                "ty": { "Array": { "Single": { "name": "Color", "kind": "External", "generics": [] } } },
+                "doc": ""
+            }]
+        });
+        assert_eq!(errors(&schema, &valid), Vec::<String>::new());
+        let mut wrong_kind = valid.clone();
+        wrong_kind["given_types"][0]["Enum"]["ty"]["Single"]["kind"] = json!("Imported");
+        assert!(!schema.is_valid(&wrong_kind));
+        let mut unknown_variant = valid.clone();
+        unknown_variant["global_instances_off"][0]["ty"] = json!({ "List": [] });
+        assert!(!schema.is_valid(&unknown_variant));
+        let mut missing_field = valid;
+        missing_field["given_types"][0]["Enum"]
+            .as_object_mut()
+            .unwrap()
+            .remove("variants");
+        assert!(!schema.is_valid(&missing_field));
+    }
+
+    #[test]
+    fn written_configs_match_the_config_schema() {
+        let schema = compile(&config_schema().unwrap());
+        let config = serde_json::to_value(Config::default()).unwrap();
+        assert_eq!(errors(&schema, &config), Vec::<String>::new());
+        let mut without_addon = config.clone();
+        without_addon["lua_addon"] = json!({ "enabled": false });
+        assert_eq!(errors(&schema, &without_addon), Vec::<String>::new());
+        let mut wrong_template = config;
+        wrong_template["doc_template"] = json!("FromNothing");
+        assert!(!schema.is_valid(&wrong_template));
+    }
+}
@@ -0,0 +1,242 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::Context;
+
+use crate::{app::RunArgs, watch::watch};
+
+/// Url the live reload script asks for the current build number
+const BUILD_URL: &str = "/__tealr_doc_gen/build";
+
+const LIVE_RELOAD_SCRIPT: &str = r#"<script>
+(() => {
+    let build = null
+    setInterval(() => {
+        fetch("/__tealr_doc_gen/build")
+            .then(x => x.text())
+            .then(x => {
+                if (build !== null && build !== x) {
+                    location.reload()
+                }
+                build = x
+            })
+            .catch(() => {})
+    }, 1000)
+})()
+</script>"#;
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|x| x.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css",
+        Some("js") => "text/javascript",
+        Some("json") => "application/json",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("zip") => "application/zip",
+        Some("lua") | Some("tl") => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Decodes the `%xx` escapes in a url. A `%` that isn't followed by 2 hex digits is kept as is
+fn decode_url(url: &str) -> String {
+    let url = url.as_bytes();
+    let mut bytes = Vec::with_capacity(url.len());
+    let mut i = 0;
+    while i < url.len() {
+        let hex = url
+            .get(i + 1..i + 3)
+            .filter(|x| url[i] == b'%' && x.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|x| u8::from_str_radix(std::str::from_utf8(x).ok()?, 16).ok());
+        match hex {
+            Some(x) => {
+                bytes.push(x);
+                i += 3;
+            }
+            None => {
+                bytes.push(url[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Turns the requested url into a path inside of `serve_from`, refusing anything that tries to leave it
+fn file_for_url(serve_from: &Path, url: &str) -> Option<PathBuf> {
+    let url = url.split(['?', '#']).next().unwrap_or_default();
+    let url = decode_url(url);
+    let mut path = serve_from.to_path_buf();
+    for component in Path::new(url.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(x) => path.push(x),
+            Component::CurDir => (),
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    if path.is_dir() {
+        path.push("index.html");
+    }
+    Some(path)
+}
+
+fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+fn handle_connection(
+    mut stream: TcpStream,
+    serve_from: &Path,
+    root: &str,
+    build: &AtomicU64,
+) -> std::io::Result<()> {
+    let mut request_line = String::new();
+    let mut reader = BufReader::new(stream.try_clone()?);
+    reader.read_line(&mut request_line)?;
+    //the headers are not needed, but they still need to be read before answering
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(url)) = (parts.next(), parts.next()) else {
+        return respond(&mut stream, "400 Bad Request", "text/plain", b"Bad request");
+    };
+    if method != "GET" {
+        return respond(
+            &mut stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            b"Method not allowed",
+        );
+    }
+    if url == BUILD_URL {
+        let build = build.load(Ordering::SeqCst).to_string();
+        return respond(&mut stream, "200 OK", "text/plain", build.as_bytes());
+    }
+    if url == "/" && !root.is_empty() {
+        let location = format!("/{}/index.html", root.trim_matches('/'));
+        write!(
+            stream,
+            "HTTP/1.1 302 Found\r\nLocation: {location}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        )?;
+        return stream.flush();
+    }
+    let Some(path) = file_for_url(serve_from, url) else {
+        return respond(&mut stream, "403 Forbidden", "text/plain", b"Forbidden");
+    };
+    match std::fs::read(&path) {
+        Ok(mut body) => {
+            let content_type = content_type(&path);
+            if content_type.starts_with("text/html") {
+                body.extend_from_slice(LIVE_RELOAD_SCRIPT.as_bytes());
+            }
+            respond(&mut stream, "200 OK", content_type, &body)
+        }
+        Err(_) => respond(&mut stream, "404 Not Found", "text/plain", b"Not found"),
+    }
+}
+
+/// Generates the documentation, serves it on localhost and regenerates it when any of the inputs change.
+///
+/// Pages served this way get a small script injected that reloads them once the documentation got generated again.
+pub(crate) fn serve(args: RunArgs, port: u16) -> Result<(), anyhow::Error> {
+    let paths = args.load_paths()?;
+    let serve_from = PathBuf::from(&paths.build_dir);
+    let root = paths.root.clone();
+    let build = Arc::new(AtomicU64::new(0));
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .with_context(|| format!("Could not listen on port {port}"))?;
+
+    let build_counter = build.clone();
+    std::thread::spawn(move || {
+        if let Err(x) = watch(args, || {
+            build_counter.fetch_add(1, Ordering::SeqCst);
+        }) {
+            eprintln!("Error: {x:?}");
+        }
+    });
+    eprintln!(
+        "Serving documentation at http://127.0.0.1:{port}/{}",
+        root.trim_matches('/')
+    );
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(x) => x,
+            Err(x) => {
+                eprintln!("Could not accept connection: {x}");
+                continue;
+            }
+        };
+        let serve_from = serve_from.clone();
+        let root = root.clone();
+        let build = build.clone();
+        std::thread::spawn(move || {
+            if let Err(x) = handle_connection(stream, &serve_from, &root, &build) {
+                eprintln!("Error while answering request: {x}");
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_folder;
+
+    #[test]
+    fn urls_get_decoded() {
+        assert_eq!(decode_url("/my%20page.html"), "/my page.html");
+        assert_eq!(decode_url("/%C3%A9t%c3%a9"), "/été");
+        //invalid escapes are kept instead of dropping what follows them
+        assert_eq!(decode_url("/100%"), "/100%");
+        assert_eq!(decode_url("/50%zz/%4"), "/50%zz/%4");
+        assert_eq!(decode_url("/%+1"), "/%+1");
+        assert_eq!(decode_url("/%%41"), "/%A");
+    }
+
+    #[test]
+    fn urls_can_not_leave_the_served_folder() {
+        let folder = test_folder("urls_can_not_leave_the_served_folder");
+        std::fs::create_dir_all(folder.join("types")).unwrap();
+        assert_eq!(
+            file_for_url(&folder, "/types/Foo.html?query#anchor"),
+            Some(folder.join("types/Foo.html"))
+        );
+        assert_eq!(
+            file_for_url(&folder, "/./my%20page.html"),
+            Some(folder.join("my page.html"))
+        );
+        //folders serve their index.html
+        assert_eq!(file_for_url(&folder, "/"), Some(folder.join("index.html")));
+        assert_eq!(
+            file_for_url(&folder, "/types"),
+            Some(folder.join("types/index.html"))
+        );
+        assert_eq!(file_for_url(&folder, "/../secret.txt"), None);
+        assert_eq!(file_for_url(&folder, "/types/../../secret.txt"), None);
+        assert_eq!(file_for_url(&folder, "/%2e%2e/secret.txt"), None);
+        assert_eq!(file_for_url(&folder, "/types%2F..%2F..%2Fsecret.txt"), None);
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+}
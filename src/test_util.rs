@@ -0,0 +1,12 @@
+use std::path::PathBuf;
+
+/// An empty folder in the temp dir, unique to the test calling it.
+///
+/// The path is canonicalized, so it can be compared against paths that got resolved
+pub(crate) fn test_folder(name: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("tealr_doc_gen_test_{}_{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path).unwrap();
+    path.canonicalize().unwrap()
+}
@@ -0,0 +1,56 @@
+use std::{
+    path::PathBuf,
+    thread::sleep,
+    time::{Duration, SystemTime},
+};
+
+use crate::{app::RunArgs, generation::run_template};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+
+fn modified_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|x| std::fs::metadata(x).and_then(|x| x.modified()).ok())
+        .collect()
+}
+
+fn wait_for_change(files: &[PathBuf]) {
+    let before = modified_times(files);
+    loop {
+        sleep(POLL_INTERVAL);
+        if modified_times(files) != before {
+            //editors tend to write files in multiple steps, so give them a moment to finish
+            sleep(POLL_INTERVAL);
+            return;
+        }
+    }
+}
+
+/// Generates the documentation, waits until one of the files it read changes and then does it again.
+///
+/// Errors are printed rather than returned, so a mistake in a template doesn't stop the watcher.
+///
+/// `on_build` gets called after every attempt at generating the documentation.
+pub(crate) fn watch(args: RunArgs, mut on_build: impl FnMut()) -> Result<(), anyhow::Error> {
+    loop {
+        let files = match args.load_paths() {
+            Ok(paths) => {
+                let mut files = paths.input_files();
+                files.push(args.config_path());
+                if let Err(x) = run_template(paths) {
+                    eprintln!("Error: {x:?}");
+                }
+                on_build();
+                files
+            }
+            Err(x) => {
+                eprintln!("Error: {x:?}");
+                vec![args.config_path()]
+            }
+        };
+        eprintln!("Waiting for changes...");
+        wait_for_change(&files);
+        eprintln!("Change detected, generating documentation again.");
+    }
+}